@@ -0,0 +1,295 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use clap::error::ErrorKind;
+
+use csvfilt::{CsvFiltError, Precedence};
+
+use compression::Compression;
+use rejects::OnError;
+use source::FileSource;
+
+pub struct Args {
+    pub sources : Vec<FileSource>,
+    pub query : String,
+    pub output : Option<PathBuf>,
+    pub delimiter : u8,
+    pub has_header : bool,
+    pub schema : Option<String>,
+    pub null_values : Vec<String>,
+    pub select : Option<String>,
+    // the name of the column naming each row's file
+    pub tag_source : Option<String>,
+    pub output_compression : Compression,
+    pub precedence : Precedence,
+    pub on_error : OnError,
+    pub threads : usize,
+    pub mmap : bool
+}
+
+fn command() -> Command {
+    Command::new("csvfilt")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Filters the rows of typed CSV files with a query.\n\n\
+                The header of each input names every column with its type, e.g. \
+                stock[string],price[float],size[int],executed[bool]. Date, time and timestamp \
+                columns are ISO-8601 unless a format follows the type, e.g. ts[timestamp:%Y%m%d-%H:%M:%S].")
+        .override_usage("csvfilt [OPTIONS] <QUERY> [FILE]...\n       csvfilt [OPTIONS] --query <QUERY> [FILE]...")
+        .arg(Arg::new("args")
+            .value_name("QUERY|FILE")
+            .help("The query, e.g. 'price > 100 && executed = true', unless --query is given, \
+                   followed by input files or glob patterns; '-' or no files reads from stdin")
+            .value_parser(clap::value_parser!(OsString))
+            .num_args(0..))
+        .arg(Arg::new("query")
+            .short('q')
+            .long("query")
+            .value_name("QUERY")
+            .help("Query to filter rows with, instead of the first positional argument"))
+        .arg(Arg::new("input")
+            .short('i')
+            .long("input")
+            .value_name("FILE")
+            .help("Input file or glob pattern, may be given more than once")
+            .value_parser(clap::value_parser!(OsString))
+            .action(ArgAction::Append))
+        .arg(Arg::new("output")
+            .short('o')
+            .long("output")
+            .value_name("FILE")
+            .help("Write matching rows to FILE instead of stdout")
+            .value_parser(clap::value_parser!(PathBuf)))
+        .arg(Arg::new("delimiter")
+            .short('d')
+            .long("delimiter")
+            .value_name("CHAR")
+            .help("Field delimiter for input and output")
+            .default_value(","))
+        .arg(Arg::new("no-header")
+            .long("no-header")
+            .help("Inputs have no header row and no header is written; requires --schema")
+            .action(ArgAction::SetTrue)
+            .requires("schema"))
+        .arg(Arg::new("schema")
+            .long("schema")
+            .value_name("HEADER")
+            .help("Typed header to use instead of the header row of the inputs, e.g. 'stock[string],size[int]'"))
+        .arg(Arg::new("null-values")
+            .long("null-values")
+            .value_name("VALUES")
+            .help("Comma separated cell values that are null in columns whose type is marked nullable, \
+                   as in size[int?], e.g. 'NA,NULL,' for NA, NULL and empty cells")
+            .default_value(""))
+        .arg(Arg::new("select")
+            .short('s')
+            .long("select")
+            .value_name("COLUMNS")
+            .help("Columns to output, in order, e.g. 'stock,price', 'stock..size' or '!executed'"))
+        .arg(Arg::new("tag-source")
+            .long("tag-source")
+            .value_name("NAME")
+            .help("Append a string column naming the file each row came from, called 'source' unless \
+                   named with --tag-source=NAME")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("source"))
+        .arg(Arg::new("output-compression")
+            .long("output-compression")
+            .value_name("FORMAT")
+            .help("Compress the output")
+            .value_parser(["none", "gzip", "zstd", "bzip2"])
+            .default_value("none"))
+        .arg(Arg::new("legacy-precedence")
+            .long("legacy-precedence")
+            .help("Give && and || equal precedence and group them to the right, as older versions did, \
+                   so 'a && b || c' means 'a && (b || c)'")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("on-error")
+            .long("on-error")
+            .value_name("POLICY")
+            .help("What to do with rows that have the wrong number of fields or cells that can't be read as \
+                   their type: 'fail' stops the run, 'skip' leaves them out and 'reject:<path>' writes them \
+                   to a CSV file with the reason in an extra column")
+            .default_value("fail"))
+        .arg(Arg::new("threads")
+            .short('j')
+            .long("threads")
+            .value_name("N")
+            .help("Run the query on N threads while reading and writing on another; output keeps the input order")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .default_value("1"))
+        .arg(Arg::new("mmap")
+            .long("mmap")
+            .help("Read uncompressed input files by mapping them into memory rather than through a buffer. \
+                   With --threads, each thread then parses its own part of them rather than parsing them \
+                   all on one. Compressed files and stdin are read as usual. Input files must not be \
+                   truncated or rewritten while csvfilt runs, which can crash it")
+            .action(ArgAction::SetTrue))
+}
+
+impl Args {
+    // The header of the --tag-source column, which mustn't have the name of an output column
+    pub fn tag_header(&self, output_header:&[String]) -> Result<Option<String>, CsvFiltError> {
+        let name =
+            match self.tag_source {
+                Some(ref name) => name,
+                None => return Ok(None)
+            };
+        if output_header.iter().any(|h|{ h.split('[').next() == Some(name.as_str()) }) {
+            return Err(CsvFiltError::Usage(format!(
+                "The output already has a column called '{}', give --tag-source another name with --tag-source=NAME", name)));
+        }
+        Ok(Some(format!("{}[string]", name)))
+    }
+}
+
+fn parse_delimiter(d:&str) -> Option<u8> {
+    match d.as_bytes() {
+        [b] => Some(*b),
+        b"\\t" => Some(b'\t'),
+        _ => None
+    }
+}
+
+fn from_matches(cmd:&mut Command, m:&ArgMatches) -> Result<Args, Box<dyn Error>> {
+    let mut positional = m.get_many::<OsString>("args").into_iter().flatten().cloned();
+
+    let query =
+        match m.get_one::<String>("query") {
+            Some(q) => q.clone(),
+            None => {
+                let q = positional.next().ok_or_else(||{
+                    cmd.error(ErrorKind::MissingRequiredArgument, "a query is required")
+                })?;
+                q.into_string().map_err(|_|{
+                    cmd.error(ErrorKind::InvalidUtf8, "the query is not valid UTF-8")
+                })?
+            }
+        };
+
+    let delimiter_arg = m.get_one::<String>("delimiter").unwrap();
+    let delimiter = parse_delimiter(delimiter_arg).ok_or_else(||{
+        cmd.error(ErrorKind::ValueValidation, format!("expected a single byte delimiter, got '{}'", delimiter_arg))
+    })?;
+
+    let mut sources = Vec::new();
+    let paths = 
+        positional.chain(m.get_many::<OsString>("input").into_iter().flatten().cloned());
+    for p in paths {
+        sources.extend(FileSource::from_arg(p)?);
+    }
+    if sources.is_empty() {
+        sources.push(FileSource::Stdin);
+    }
+
+    let output_compression =
+        Compression::from_name(m.get_one::<String>("output-compression").unwrap())?;
+    let on_error = OnError::from_arg(m.get_one::<String>("on-error").unwrap())?;
+
+    Ok(Args {
+        sources,
+        query,
+        output : m.get_one::<PathBuf>("output").cloned(),
+        delimiter,
+        has_header : !m.get_flag("no-header"),
+        schema : m.get_one::<String>("schema").cloned(),
+        null_values : m.get_one::<String>("null-values").unwrap().split(',').map(|v|{ v.to_owned() }).collect(),
+        select : m.get_one::<String>("select").cloned(),
+        tag_source : m.get_one::<String>("tag-source").cloned(),
+        output_compression,
+        precedence : if m.get_flag("legacy-precedence") { Precedence::Legacy } else { Precedence::Standard },
+        on_error,
+        threads : *m.get_one::<u32>("threads").unwrap() as usize,
+        mmap : m.get_flag("mmap")
+    })
+}
+
+// Usage errors, --help and --version are reported by clap, which exits the process
+pub fn read_args() -> Result<Args, CsvFiltError> {
+    let mut cmd = command();
+    let matches = cmd.get_matches_mut();
+    from_matches(&mut cmd, &matches).map_err(|e|{
+        match e.downcast::<clap::Error>() {
+            Ok(usage) => usage.exit(),
+            Err(e) =>
+                match e.downcast::<CsvFiltError>() {
+                    Ok(e) => *e,
+                    Err(e) => CsvFiltError::Usage(e.to_string())
+                }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cli::{command, from_matches, parse_delimiter, Args};
+    use source::FileSource;
+    use std::error::Error;
+
+    fn parse(args:Vec<&str>) -> Result<Args, Box<dyn Error>> {
+        let mut cmd = command();
+        let m = cmd.try_get_matches_from_mut(args)?;
+        from_matches(&mut cmd, &m)
+    }
+
+    #[test]
+    fn query_can_be_positional_or_named() {
+        let positional = parse(vec!("csvfilt", "a = 1", "-")).unwrap();
+        assert_eq!("a = 1", positional.query);
+        assert_eq!(1, positional.sources.len());
+
+        let named = parse(vec!("csvfilt", "-q", "a = 1", "-", "-i", "-")).unwrap();
+        assert_eq!("a = 1", named.query);
+        assert_eq!(2, named.sources.len());
+    }
+
+    #[test]
+    fn no_files_reads_stdin() {
+        let args = parse(vec!("csvfilt", "a = 1")).unwrap();
+        match args.sources[..] {
+            [FileSource::Stdin] => (),
+            _ => panic!("expected stdin")
+        }
+    }
+
+    #[test]
+    fn missing_query_is_a_usage_error() {
+        let e = parse(vec!("csvfilt")).err().unwrap();
+        assert!(e.downcast_ref::<clap::Error>().is_some());
+    }
+
+    #[test]
+    fn no_header_requires_schema() {
+        assert!(command().try_get_matches_from(vec!("csvfilt", "--no-header", "a = 1")).is_err());
+        assert!(command().try_get_matches_from(vec!("csvfilt", "--no-header", "--schema", "a[int]", "a = 1")).is_ok());
+    }
+
+    #[test]
+    fn tag_source_columns_can_be_named_but_not_clash() {
+        let header = vec!("stock[string]".to_owned(), "source[string]".to_owned());
+        assert_eq!(None, parse(vec!("csvfilt", "a = 1")).unwrap().tag_header(&header).unwrap());
+        // the name has to be given with =, so it can't take a following file for one
+        let named = parse(vec!("csvfilt", "--tag-source=file", "a = 1", "x.csv")).unwrap();
+        assert_eq!(Some("file[string]".to_owned()), named.tag_header(&header).unwrap());
+        assert_eq!(1, named.sources.len());
+
+        let e = parse(vec!("csvfilt", "--tag-source", "a = 1")).unwrap().tag_header(&header).err().unwrap();
+        assert_eq!("The output already has a column called 'source', give --tag-source another name with --tag-source=NAME", e.to_string());
+    }
+
+    #[test]
+    fn null_values_default_to_empty_cells() {
+        assert_eq!(vec!(""), parse(vec!("csvfilt", "a = 1")).unwrap().null_values);
+        assert_eq!(vec!("NA", "NULL", ""), parse(vec!("csvfilt", "--null-values", "NA,NULL,", "a = 1")).unwrap().null_values);
+    }
+
+    #[test]
+    fn delimiters_must_be_one_byte() {
+        assert_eq!(Some(b';'), parse_delimiter(";"));
+        assert_eq!(Some(b'\t'), parse_delimiter("\\t"));
+        assert_eq!(None, parse_delimiter("ab"));
+        assert!(parse(vec!("csvfilt", "-d", "ab", "a = 1")).is_err());
+    }
+}
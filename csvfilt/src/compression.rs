@@ -0,0 +1,196 @@
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use bzip2;
+use flate2;
+use zstd;
+
+use csvfilt::{CsvFiltError, Result};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+    Plain,
+    Gzip,
+    Zstd,
+    Bzip2
+}
+
+const GZIP_MAGIC : &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC : &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+// followed by the block size, '1' to '9', so plain CSV starting with "BZh" isn't mistaken for it
+const BZIP2_MAGIC : &[u8] = b"BZh";
+
+impl Compression {
+    pub fn from_name(name:&str) -> Result<Compression> {
+        match name {
+            "none" => Ok(Compression::Plain),
+            "gz" | "gzip" => Ok(Compression::Gzip),
+            "zst" | "zstd" => Ok(Compression::Zstd),
+            "bz2" | "bzip2" => Ok(Compression::Bzip2),
+            x => Err(CsvFiltError::Usage(format!("Unknown compression '{}', expected one of none, gzip, zstd, bzip2", x)))
+        }
+    }
+
+    fn from_magic(bytes:&[u8]) -> Option<Compression> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        }
+        else if bytes.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        }
+        else if bytes.starts_with(BZIP2_MAGIC) && matches!(bytes.get(BZIP2_MAGIC.len()), Some(b'1'..=b'9')) {
+            Some(Compression::Bzip2)
+        }
+        else {
+            None
+        }
+    }
+
+    fn from_extension(path:&Path) -> Option<Compression> {
+        match path.extension().and_then(|e|{ e.to_str() }) {
+            Some("gz") => Some(Compression::Gzip),
+            Some("zst") => Some(Compression::Zstd),
+            Some("bz2") => Some(Compression::Bzip2),
+            _ => None
+        }
+    }
+
+    // The compression of an input starting with `head`, from its magic bytes if it has any
+    // and otherwise from the file extension
+    pub fn detect(head:&[u8], path:Option<&Path>) -> Compression {
+        Compression::from_magic(head)
+            .or_else(||{ path.and_then(Compression::from_extension) })
+            .unwrap_or(Compression::Plain)
+    }
+}
+
+// Sniffs the first few bytes of the input for a known compression format, falling back
+// to the file extension, and wraps the input in a streaming decoder if one is found.
+pub fn decompress(input : Box<dyn Read>, path : Option<&Path>) -> Result<Box<dyn Read>> {
+    let mut buffered = BufReader::new(input);
+    let read_error = |e|{
+        let name = path.map_or("-".to_owned(), |p|{ p.display().to_string() });
+        CsvFiltError::io(format!("Could not read '{}'", name), e)
+    };
+
+    let compression = {
+        let head = buffered.fill_buf().map_err(read_error)?;
+        Compression::detect(head, path)
+    };
+
+    match compression {
+        Compression::Plain => Ok(Box::new(buffered)),
+        Compression::Gzip => Ok(Box::new(flate2::bufread::MultiGzDecoder::new(buffered))),
+        Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(buffered).map_err(read_error)?)),
+        Compression::Bzip2 => Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(buffered)))
+    }
+}
+
+// An output stream that may be compressed. Encoders need to write a trailer once all the
+// data is in, so `finish` has to be called rather than relying on drop.
+pub enum CompressedWriter<W : Write> {
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    Bzip2(bzip2::write::BzEncoder<W>)
+}
+
+impl<W : Write> CompressedWriter<W> {
+    pub fn new(inner : W, compression : Compression) -> Result<CompressedWriter<W>> {
+        match compression {
+            Compression::Plain =>
+                Ok(CompressedWriter::Plain(inner)),
+            Compression::Gzip =>
+                Ok(CompressedWriter::Gzip(flate2::write::GzEncoder::new(inner, flate2::Compression::default()))),
+            Compression::Zstd =>
+                Ok(CompressedWriter::Zstd(zstd::stream::write::Encoder::new(inner, 0)
+                    .map_err(|e|{ CsvFiltError::io("Could not start compressing the output".to_owned(), e) })?)),
+            Compression::Bzip2 =>
+                Ok(CompressedWriter::Bzip2(bzip2::write::BzEncoder::new(inner, bzip2::Compression::default())))
+        }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        let mut inner =
+            match self {
+                CompressedWriter::Plain(w) => w,
+                CompressedWriter::Gzip(e) => e.finish()?,
+                CompressedWriter::Zstd(e) => e.finish()?,
+                CompressedWriter::Bzip2(e) => e.finish()?
+            };
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl<W : Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            CompressedWriter::Plain(ref mut w) => w.write(buf),
+            CompressedWriter::Gzip(ref mut e) => e.write(buf),
+            CompressedWriter::Zstd(ref mut e) => e.write(buf),
+            CompressedWriter::Bzip2(ref mut e) => e.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            CompressedWriter::Plain(ref mut w) => w.flush(),
+            CompressedWriter::Gzip(ref mut e) => e.flush(),
+            CompressedWriter::Zstd(ref mut e) => e.flush(),
+            CompressedWriter::Bzip2(ref mut e) => e.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compression::{decompress, Compression, CompressedWriter};
+    use std::io::{Cursor, Read, Write};
+    use std::path::Path;
+
+    const CSV : &str = "a[int],b[string]\n1,x\n2,y\n";
+
+    fn round_trip(compression : Compression, path : Option<&Path>) -> String {
+        let mut w = CompressedWriter::new(Vec::new(), compression).unwrap();
+        w.write_all(CSV.as_bytes()).unwrap();
+        let bytes = w.finish().unwrap();
+
+        let mut out = String::new();
+        decompress(Box::new(Cursor::new(bytes)), path).unwrap().read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn every_compression_round_trips() {
+        for c in [Compression::Plain, Compression::Gzip, Compression::Zstd, Compression::Bzip2].iter() {
+            assert_eq!(CSV, round_trip(*c, None));
+        }
+    }
+
+    #[test]
+    fn magic_bytes_win_over_extension() {
+        assert_eq!(CSV, round_trip(Compression::Gzip, Some(Path::new("trades.csv"))));
+        assert_eq!(CSV, round_trip(Compression::Zstd, Some(Path::new("trades.csv.bz2"))));
+    }
+
+    #[test]
+    fn plain_files_starting_like_bzip2_are_read_as_plain() {
+        let csv = "BZh.L[string]\nBZhx\n";
+        let mut out = String::new();
+        decompress(Box::new(Cursor::new(csv.as_bytes().to_vec())), None).unwrap().read_to_string(&mut out).unwrap();
+        assert_eq!(csv, out);
+        assert_eq!(Compression::Plain, Compression::detect(b"BZh", None));
+        assert_eq!(Compression::Bzip2, Compression::detect(b"BZh9", None));
+    }
+
+    #[test]
+    fn compression_names_are_recognised() {
+        assert_eq!(Compression::Gzip, Compression::from_name("gz").unwrap());
+        assert_eq!(Compression::Zstd, Compression::from_name("zstd").unwrap());
+        assert_eq!(Compression::Bzip2, Compression::from_name("bzip2").unwrap());
+        assert_eq!(Compression::Plain, Compression::from_name("none").unwrap());
+        assert!(Compression::from_name("lz4").is_err());
+    }
+}
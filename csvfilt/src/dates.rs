@@ -0,0 +1,126 @@
+use chrono::format::{parse, Item, Parsed, StrftimeItems};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+
+use error::{CsvFiltError, Result};
+use schema::{SupportedColType, ValueMaker};
+use value::Value;
+
+// The parsed form of a strftime format from a header, such as `%Y%m%d-%H:%M:%S%.f`
+fn items(format:&str, type_name:String) -> Result<Vec<Item<'static>>> {
+    let items : Vec<Item<'static>> = StrftimeItems::new(format).map(|i|{ i.to_owned() }).collect();
+    if items.iter().any(|i|{ *i == Item::Error }) {
+        return Err(CsvFiltError::Schema(format!("'{}' is not a valid format for a {}", format, type_name)));
+    }
+    Ok(items)
+}
+
+// Reads cells written in `format`, with `read` picking the type's value out of what was parsed
+fn formatted<T, F>(format:&str, read:F) -> Result<ValueMaker>
+    where T : SupportedColType, F : Fn(&Parsed) -> Option<T> + Send + Sync + 'static
+{
+    let items = items(format, T::str_type())?;
+    Ok(Box::new(move |s|{
+        let mut parsed = Parsed::new();
+        parse(&mut parsed, s, items.iter()).ok()
+            .and_then(|_|{ read(&parsed) })
+            .map(T::into_value)
+            .ok_or_else(||{ T::parse_err(s) })
+    }))
+}
+
+impl SupportedColType for NaiveDate {
+    fn str_type() -> String {
+        "date".to_owned()
+    }
+
+    fn into_value(self) -> Value {
+        Value::Date(self)
+    }
+
+    fn formatted(format:&str) -> Result<ValueMaker> {
+        formatted(format, |p|{ p.to_naive_date().ok() })
+    }
+}
+
+impl SupportedColType for NaiveTime {
+    fn str_type() -> String {
+        "time".to_owned()
+    }
+
+    fn into_value(self) -> Value {
+        Value::Time(self)
+    }
+
+    fn formatted(format:&str) -> Result<ValueMaker> {
+        formatted(format, |p|{ p.to_naive_time().ok() })
+    }
+}
+
+// Timestamps are compared as instants, so ones written with different offsets compare by
+// when they happened. A timestamp written without an offset is taken to be in UTC.
+impl SupportedColType for DateTime<Utc> {
+    fn str_type() -> String {
+        "timestamp".to_owned()
+    }
+
+    fn into_value(self) -> Value {
+        Value::Timestamp(self)
+    }
+
+    fn make_value(s:&str) -> Result<Value> {
+        s.parse::<DateTime<Utc>>()
+            .or_else(|_|{ s.parse::<NaiveDateTime>().map(|t|{ t.and_utc() }) })
+            .map(Self::into_value)
+            .map_err(|_|{ Self::parse_err(s) })
+    }
+
+    fn formatted(format:&str) -> Result<ValueMaker> {
+        formatted(format, |p|{
+            match p.offset() {
+                Some(_) => p.to_datetime().ok().map(|t|{ t.with_timezone(&Utc) }),
+                None => p.to_naive_datetime_with_offset(0).ok().map(|t|{ t.and_utc() })
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+
+    use dates::items;
+    use schema::SupportedColType;
+    use value::Value;
+
+    fn timestamp(s:&str) -> Value {
+        Value::Timestamp(s.parse::<DateTime<Utc>>().unwrap())
+    }
+
+    #[test]
+    fn iso_8601_is_read_by_default() {
+        assert_eq!(Value::Date(NaiveDate::from_ymd_opt(2026, 10, 18).unwrap()), NaiveDate::make_value("2026-10-18").unwrap());
+        assert_eq!(Value::Time(NaiveTime::from_hms_milli_opt(9, 30, 0, 250).unwrap()), NaiveTime::make_value("09:30:00.250").unwrap());
+        assert_eq!(timestamp("2026-10-18T09:00:00Z"), DateTime::<Utc>::make_value("2026-10-18T09:00:00").unwrap());
+        assert_eq!(timestamp("2026-10-18T09:00:00Z"), DateTime::<Utc>::make_value("2026-10-18T11:00:00+02:00").unwrap());
+        assert!(NaiveDate::make_value("18/10/2026").is_err());
+    }
+
+    #[test]
+    fn cells_are_read_in_the_header_format() {
+        let ts = DateTime::<Utc>::formatted("%Y%m%d-%H:%M:%S%.f").unwrap();
+        assert_eq!(timestamp("2026-10-18T09:00:00.5Z"), ts("20261018-09:00:00.5").unwrap());
+        assert!(ts("2026-10-18T09:00:00Z").is_err());
+
+        let with_offset = DateTime::<Utc>::formatted("%d/%m/%Y %H:%M %z").unwrap();
+        assert_eq!(timestamp("2026-10-18T09:00:00Z"), with_offset("18/10/2026 10:00 +0100").unwrap());
+
+        let date = NaiveDate::formatted("%d/%m/%Y").unwrap();
+        assert_eq!(Value::Date(NaiveDate::from_ymd_opt(2026, 10, 18).unwrap()), date("18/10/2026").unwrap());
+    }
+
+    #[test]
+    fn bad_formats_are_schema_errors() {
+        let e = items("%Y-%Q", "date".to_owned()).err().unwrap();
+        assert_eq!("'%Y-%Q' is not a valid format for a date", e.to_string());
+    }
+}
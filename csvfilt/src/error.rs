@@ -0,0 +1,245 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use csv;
+
+use query::{ParseError, Span};
+
+/// Everything that can make csvfilt fail, each kind with its own exit code
+#[derive(Debug)]
+pub enum CsvFiltError {
+    /// command line values clap can't check, e.g. --select with a SELECT statement
+    Usage(String),
+    /// a header or --schema that can't be read as typed columns
+    Schema(String),
+    /// a later input whose schema differs from the first one's
+    SchemaMismatch { name : String, schema : String, first_name : String, first_schema : String },
+    /// query text that can't be split into tokens
+    Tokenise(String),
+    /// a query that doesn't follow the grammar or names unknown columns
+    Parse(ParseError),
+    /// an operation the types involved don't support, e.g. comparing an int column with a
+    /// string column or adding strings
+    TypeMismatch { op : String, left : String, right : String },
+    /// a value that isn't valid for its type; column and record are None for constants in the query
+    CellParse { column : Option<String>, record : Option<RecordPosition>, value : String, type_name : String },
+    /// a record with more or fewer fields than the schema has columns
+    FieldCount { expected : usize, found : usize, record : Option<RecordPosition> },
+    /// overflow or division by zero in a computed column
+    Arithmetic(String),
+    /// any of the above found in one part of the query, which is shown underlined
+    InQuery { query : String, span : Option<Span>, source : Box<CsvFiltError> },
+    Io { context : String, source : io::Error },
+    Csv(csv::Error)
+}
+
+/// Where a record is, for finding bad data in large inputs
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordPosition {
+    pub file : String,
+    /// 1-based line the record starts on
+    pub line : u64,
+    pub byte : u64
+}
+
+impl RecordPosition {
+    pub fn new(file:&str, position:&csv::Position) -> RecordPosition {
+        RecordPosition { file : file.to_owned(), line : position.line(), byte : position.byte() }
+    }
+}
+
+impl fmt::Display for RecordPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} (byte {}) of '{}'", self.line, self.byte, self.file)
+    }
+}
+
+impl CsvFiltError {
+    pub fn io(context:String, source:io::Error) -> CsvFiltError {
+        CsvFiltError::Io { context, source }
+    }
+
+    /// A parse error, pointing at where it is in `query`
+    pub fn in_query(query:&str, e:ParseError) -> CsvFiltError {
+        let span = e.span;
+        let source = e.into_error();
+        CsvFiltError::InQuery { query : query.to_owned(), span, source : Box::new(source) }
+    }
+
+    /// Names the column a cell error came from, other errors are unchanged
+    pub fn in_column(self, name:&str) -> CsvFiltError {
+        match self {
+            CsvFiltError::CellParse { column : None, record, value, type_name } =>
+                CsvFiltError::CellParse { column : Some(name.to_owned()), record, value, type_name },
+            e => e
+        }
+    }
+
+    /// Places a cell or field count error at the record being read from `file`, other errors
+    /// are unchanged
+    pub fn at_record(self, file:&str, position:Option<&csv::Position>) -> CsvFiltError {
+        match (self, position) {
+            (CsvFiltError::CellParse { column, record : None, value, type_name }, Some(p)) =>
+                CsvFiltError::CellParse { column, record : Some(RecordPosition::new(file, p)), value, type_name },
+            (CsvFiltError::FieldCount { expected, found, record : None }, Some(p)) =>
+                CsvFiltError::FieldCount { expected, found, record : Some(RecordPosition::new(file, p)) },
+            (e, _) => e
+        }
+    }
+
+    /// Errors in the data of a single row, which --on-error can skip past
+    pub fn is_bad_row(&self) -> bool {
+        matches!(*self, CsvFiltError::CellParse { .. } | CsvFiltError::FieldCount { .. })
+    }
+
+    /// The process exit code the csvfilt tool uses for this kind of error
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            CsvFiltError::Usage(_) => 2,
+            CsvFiltError::Io { .. } => 3,
+            CsvFiltError::Csv(_) => 4,
+            CsvFiltError::Schema(_) => 5,
+            CsvFiltError::SchemaMismatch { .. } => 6,
+            CsvFiltError::Tokenise(_) => 7,
+            CsvFiltError::Parse(_) => 8,
+            CsvFiltError::TypeMismatch { .. } => 9,
+            CsvFiltError::CellParse { .. } => 10,
+            CsvFiltError::Arithmetic(_) => 11,
+            CsvFiltError::FieldCount { .. } => 12,
+            CsvFiltError::InQuery { ref source, .. } => source.exit_code()
+        }
+    }
+}
+
+// The line of `query` holding `span`, with the span underlined:
+//
+//   price ( 100
+//         ^
+fn underline(f:&mut fmt::Formatter, query:&str, span:Option<Span>) -> fmt::Result {
+    let (start, end) =
+        match span {
+            Some(span) => (span.start, span.end),
+            None => (query.len(), query.len())
+        };
+    // the query may span lines, only the line the error starts on is shown
+    let line_start = query[..start].rfind('\n').map_or(0, |i|{ i + 1 });
+    let line_end = query[start..].find('\n').map_or(query.len(), |i|{ start + i });
+    let line = &query[line_start..line_end];
+
+    let indent = query[line_start..start].chars().count();
+    let width = query[start..end.min(line_end)].chars().count().max(1);
+
+    write!(f, "\n  {}\n  {}{}", line, " ".repeat(indent), "^".repeat(width))
+}
+
+impl fmt::Display for CsvFiltError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CsvFiltError::Usage(ref s) | CsvFiltError::Schema(ref s) |
+                CsvFiltError::Tokenise(ref s) | CsvFiltError::Arithmetic(ref s) => write!(f, "{}", s),
+            CsvFiltError::SchemaMismatch { ref name, ref schema, ref first_name, ref first_schema } =>
+                write!(f, "Schema of '{}' ({}) does not match schema of '{}' ({})", name, schema, first_name, first_schema),
+            CsvFiltError::Parse(ref e) => write!(f, "{}", e),
+            CsvFiltError::TypeMismatch { ref op, ref left, ref right } =>
+                if left == right {
+                    write!(f, "{} does not support {}", left, op)
+                }
+                else {
+                    write!(f, "Can't apply {} to {} and {}", op, left, right)
+                },
+            CsvFiltError::CellParse { ref column, ref record, ref value, ref type_name } => {
+                write!(f, "Could not make a {} from '{}'", type_name, value)?;
+                if let Some(ref c) = *column {
+                    write!(f, " in column {}", c)?;
+                }
+                if let Some(ref r) = *record {
+                    write!(f, " at {}", r)?;
+                }
+                Ok(())
+            }
+            CsvFiltError::FieldCount { expected, found, ref record } => {
+                write!(f, "Expected {} fields, found {}", expected, found)?;
+                if let Some(ref r) = *record {
+                    write!(f, " at {}", r)?;
+                }
+                Ok(())
+            }
+            CsvFiltError::InQuery { ref query, span, ref source } => {
+                write!(f, "{}", source)?;
+                underline(f, query, span)
+            }
+            CsvFiltError::Io { ref context, ref source } => write!(f, "{}: {}", context, source),
+            CsvFiltError::Csv(ref e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl Error for CsvFiltError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CsvFiltError::Parse(ref e) => Some(e),
+            CsvFiltError::InQuery { ref source, .. } => Some(source.as_ref()),
+            CsvFiltError::Io { ref source, .. } => Some(source),
+            CsvFiltError::Csv(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<csv::Error> for CsvFiltError {
+    fn from(e:csv::Error) -> CsvFiltError {
+        CsvFiltError::Csv(e)
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, CsvFiltError>;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io;
+
+    use csv;
+
+    use super::CsvFiltError;
+    use query::{ErrorKind, ParseError, Span};
+
+    #[test]
+    fn kinds_have_their_own_exit_codes() {
+        let io = CsvFiltError::io("Could not open 'a.csv'".to_owned(), io::Error::new(io::ErrorKind::NotFound, "gone"));
+        let cell = CsvFiltError::CellParse { column : None, record : None, value : "x".to_owned(), type_name : "int".to_owned() };
+        assert_eq!(2, CsvFiltError::Usage("bad".to_owned()).exit_code());
+        assert_eq!(3, io.exit_code());
+        assert_eq!(10, cell.exit_code());
+        assert!(io.source().is_some());
+    }
+
+    #[test]
+    fn errors_in_the_query_keep_their_kind() {
+        let tokenise = ParseError::new(ErrorKind::Tokenise("Unterminated string".to_owned()), Some(Span { start : 4, end : 6 }));
+        let e = CsvFiltError::in_query("a = 'b", tokenise);
+        assert_eq!(7, e.exit_code());
+        assert_eq!("Unterminated string\n  a = 'b\n      ^^", e.to_string());
+        assert!(e.source().is_some());
+    }
+
+    #[test]
+    fn cell_errors_say_where_the_cell_is() {
+        let e = CsvFiltError::CellParse { column : None, record : None, value : "abc".to_owned(), type_name : "float".to_owned() };
+        let mut position = csv::Position::new();
+        position.set_line(3).set_byte(41);
+        let e = e.in_column("price").at_record("trades.csv", Some(&position));
+        assert_eq!("Could not make a float from 'abc' in column price at line 3 (byte 41) of 'trades.csv'", e.to_string());
+
+        // only the innermost column is kept
+        assert_eq!(e.to_string(), e.in_column("size").to_string());
+    }
+
+    #[test]
+    fn only_cell_errors_are_placed() {
+        let e = CsvFiltError::Arithmetic("Division by zero".to_owned());
+        let e = e.in_column("price").at_record("trades.csv", Some(&csv::Position::new()));
+        assert_eq!("Division by zero", e.to_string());
+    }
+}
@@ -0,0 +1,44 @@
+//! Filters and projects rows of typed CSV, as the `csvfilt` command line tool does.
+//!
+//! The header of the input names every column with its type, e.g.
+//! `stock[string],price[float],size[int],executed[bool]`. A query is parsed against the
+//! schema read from that header, so unknown columns and type errors are found before any
+//! rows are read, and can then be run on each record:
+//!
+//! ```
+//! extern crate csv;
+//! extern crate csvfilt;
+//!
+//! use csv::StringRecord;
+//! use csvfilt::{Precedence, Schema};
+//!
+//! # fn main() -> csvfilt::Result<()> {
+//! let schema = Schema::from_header(&StringRecord::from(vec!["stock[string]", "price[float]"]))?;
+//! let query = csvfilt::parse("price > 100 && stock = VOD.L", &schema, Precedence::Standard)?;
+//!
+//! assert!(query.matches(&StringRecord::from(vec!["VOD.L", "101.5"]))?);
+//! assert!(!query.matches(&StringRecord::from(vec!["AAPL.O", "101.5"]))?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every error is a [`CsvFiltError`]; errors in a cell's value say which column the cell is
+//! in, and [`CsvFiltError::at_record`] adds where the record came from.
+
+extern crate chrono;
+extern crate csv;
+extern crate regex;
+extern crate rust_decimal;
+#[macro_use] extern crate lazy_static;
+
+mod dates;
+mod error;
+mod projection;
+mod query;
+mod schema;
+mod value;
+
+pub use error::{CsvFiltError, RecordPosition, Result};
+pub use projection::Projection;
+pub use query::{is_statement, parse, parse_statement, ErrorKind, OutputRow, ParseError, Pipeline, Precedence, QueryFn, SelectQuery, Span};
+pub use schema::Schema;
@@ -1,85 +1,169 @@
+extern crate bzip2;
+extern crate clap;
 extern crate csv;
-extern crate regex;
-#[macro_use] extern crate lazy_static;
+extern crate csvfilt;
+extern crate flate2;
+extern crate glob;
+extern crate memmap2;
+extern crate zstd;
 
-mod schema;
-mod query;
+mod cli;
+mod compression;
+mod parallel;
+mod source;
+mod rejects;
 
-use std::env;
-use std::error::Error;
-use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
 use std::process;
-use std::path::PathBuf;
 
-use schema::Schema;
+use csvfilt::{CsvFiltError, Pipeline, Projection, Result, Schema, SelectQuery};
 
-enum FileSource {
-    ReadFromFile(PathBuf)
-}
+use cli::read_args;
+use compression::CompressedWriter;
+use rejects::BadRows;
 
-struct Args {
-    source : FileSource,
-    query : String
-}
+fn run() -> Result<()> {
+    let args = read_args()?;
 
-fn read_args() -> Result<Args, Box<Error>> {
-    let args : Vec<OsString> = 
-        env::args_os().skip(1).collect(); // first arg is the exe
-    match args.len() {
-        2 => {
-            let q = args[0].clone().into_string();
-            let p = args[1].clone();
-            Ok(Args {
-                source : FileSource::ReadFromFile(From::from(p)), 
-                query : q.unwrap()
-                })
-        }
-        x => {
-            Err(
-                From::from(
-                    format!("Expected 2 args, received {} : {:?}", 
-                        x,
-                        args
-            )))
-        }
-    }
-}
+    let out : Box<dyn Write> =
+        match args.output {
+            Some(ref p) => {
+                let f = File::create(p)
+                    .map_err(|e|{ CsvFiltError::io(format!("Could not create '{}'", p.display()), e) })?;
+                Box::new(io::BufWriter::new(f))
+            }
+            None => Box::new(io::stdout())
+        };
+    let output = CompressedWriter::new(out, args.output_compression)?;
+    let mut writer = 
+        csv::WriterBuilder::new()
+            .delimiter(args.delimiter)
+            .from_writer(output);
 
-fn run() -> Result<(), Box<Error>> {
-    let args = read_args()?;
+    let mut bad_rows = BadRows::new(args.on_error.clone(), args.delimiter)?;
 
-    let mut reader = 
-        match args.source {
-            FileSource::ReadFromFile(p) => {
-                csv::Reader::from_file(p)
+    let schema_override =
+        match args.schema {
+            Some(ref s) => {
+                let mut r = csv::ReaderBuilder::new().has_headers(false).from_reader(s.as_bytes());
+                let header = r.records().next().ok_or_else(||{ CsvFiltError::Usage("--schema is empty".to_owned()) })??;
+                Some(header)
             }
-        }?;
+            None => None
+        };
 
-    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    // the schema and query come from the first file, every later file has to agree with it
+    let mut first : Option<(String, Schema, Pipeline)> = None;
 
-    let headers = reader.headers()?.clone();
+    for source in args.sources.iter() {
+        // with threads, a mapped file is only read here for its header and the workers parse
+        // the rest; on one thread it is all read here, from the map
+        let mapped = if args.mmap { source.map()? } else { None };
+        let input : Box<dyn Read> =
+            match mapped {
+                Some(ref m) => Box::new(&m[..]),
+                None => source.open()?
+            };
+        let mut reader = 
+            csv::ReaderBuilder::new()
+                .delimiter(args.delimiter)
+                .has_headers(args.has_header)
+                // field counts are checked against the schema, so that bad rows can be skipped
+                .flexible(true)
+                .from_reader(input);
 
-    let schema = Schema::from_header(&headers)?;
+        let headers =
+            match schema_override {
+                Some(ref h) => h.clone(),
+                None => reader.headers()?.clone()
+            };
 
-    writer.write(headers.iter())?;
-    
-    let q = query::parse(&args.query, &schema)?;
+        let null_values : Vec<&str> = args.null_values.iter().map(|v|{ v.as_str() }).collect();
+        let schema = Schema::from_header_with_null_values(&headers, &null_values)?;
 
-    for res in reader.records() {
-        let row = res?;
-        let matches = q.matches(&row)?;
-        if matches {
-            writer.write(row.iter())?;
+        match first {
+            None => {
+                let select =
+                    if csvfilt::is_statement(&args.query) {
+                        if args.select.is_some() {
+                            return Err(CsvFiltError::Usage("--select can't be used with a SELECT statement".to_owned()));
+                        }
+                        csvfilt::parse_statement(&args.query, &schema, args.precedence)?
+                    }
+                    else {
+                        let q = csvfilt::parse(&args.query, &schema, args.precedence)?;
+                        let projection =
+                            match args.select {
+                                Some(ref spec) => Projection::parse(spec, &schema)?,
+                                None => Projection::all(&schema)
+                            };
+                        SelectQuery::from_filter(q, projection.columns(), &schema)
+                    };
+                // no header is written when the inputs don't have one
+                if args.has_header {
+                    let tag = args.tag_header(select.header())?;
+                    writer.write_record(select.header().iter().map(|h|{ h.as_str() }).chain(tag.as_deref()))?;
+                    bad_rows.write_header(&headers)?;
+                }
+                first = Some((source.name(), schema, Pipeline::new(select)));
+            }
+            Some((ref first_name, ref first_schema, _)) => {
+                if *first_schema != schema {
+                    return Err(CsvFiltError::SchemaMismatch {
+                        name : source.name(),
+                        schema : schema.to_string(),
+                        first_name : first_name.clone(),
+                        first_schema : first_schema.to_string()
+                    });
+                }
+            }
         }
+
+        let pipeline = &mut first.as_mut().unwrap().2;
+        let name = source.name();
+        let tag = args.tag_source.as_ref().map(|_|{ name.as_str() });
+
+        if args.threads > 1 {
+            let sink = parallel::Sink::new(pipeline, &mut bad_rows, &mut writer, &name);
+            match mapped {
+                Some(ref m) => {
+                    if args.has_header {
+                        reader.headers()?;
+                    }
+                    let start = reader.position().clone();
+                    parallel::filter_mapped(&m[start.byte() as usize..], start, args.delimiter, args.threads, tag, sink)?;
+                }
+                None => parallel::filter(&mut reader, args.threads, tag, sink)?
+            }
+            continue;
+        }
+
+        // records are kept as bytes, only the fields the query uses are decoded
+        for res in reader.byte_records() {
+            if pipeline.is_done() {
+                break;
+            }
+            let row = res?;
+            if let Err(e) = pipeline.push(&row, tag, &mut writer) {
+                bad_rows.handle(&row, e.at_record(&name, row.position()))?;
+            }
+        }
+    }
+
+    if let Some((_, _, pipeline)) = first {
+        pipeline.finish(&mut writer)?;
     }
 
-    writer.flush()?;
-    Ok(())
+    let write_error = |e|{ CsvFiltError::io("Could not write output".to_owned(), e) };
+    writer.into_inner().map_err(|e|{ write_error(e.into_error()) })?.finish().map_err(write_error)?;
+    bad_rows.finish()
     }
 
 fn main() {
     if let Err(err) = run() {
-        println!("{}", err);
-        process::exit(1);
+        eprintln!("csvfilt: {}", err);
+        process::exit(err.exit_code());
     }
 }
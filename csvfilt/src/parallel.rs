@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::iter;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use csv::{ByteRecord, Position, Reader, ReaderBuilder, Writer};
+
+use csvfilt::{CsvFiltError, OutputRow, Pipeline, Result};
+
+use rejects::BadRows;
+
+// Records handed to a worker at a time, enough that passing batches around costs little
+// next to running the query on them
+const BATCH_SIZE : usize = 4096;
+
+// Bytes of a mapped file handed to a worker at a time, for it to parse itself
+const CHUNK_SIZE : usize = 1 << 20;
+
+// Batches that may be read but not yet written, per worker, which bounds memory use when
+// one batch is slow and later ones pile up behind it
+const BATCHES_IN_FLIGHT : usize = 4;
+
+type Outputs = Vec<Result<Option<OutputRow>>>;
+
+// Work for a worker: records read on the main thread, or a part of a mapped file that starts
+// and ends on record boundaries, with the position of its first byte in the file
+enum Batch<'a> {
+    Records(Vec<ByteRecord>),
+    Chunk(&'a [u8], Position)
+}
+
+// Where the results of a run go, in the order the records were read
+pub struct Sink<'a, W : Write> {
+    pipeline : &'a mut Pipeline,
+    bad_rows : &'a mut BadRows,
+    writer : &'a mut Writer<W>,
+    name : &'a str,
+    // batches that finished before an earlier one, by sequence number
+    pending : BTreeMap<usize, (Vec<ByteRecord>, Outputs, Option<csv::Error>)>,
+    next : usize
+}
+
+impl<'a, W : Write> Sink<'a, W> {
+    pub fn new(pipeline:&'a mut Pipeline, bad_rows:&'a mut BadRows, writer:&'a mut Writer<W>, name:&'a str) -> Sink<'a, W> {
+        Sink { pipeline, bad_rows, writer, name, pending : BTreeMap::new(), next : 0 }
+    }
+
+    // `error` is one met parsing a chunk after its records
+    fn add(&mut self, seq:usize, records:Vec<ByteRecord>, outputs:Outputs, error:Option<csv::Error>) -> Result<()> {
+        self.pending.insert(seq, (records, outputs, error));
+        while let Some((records, outputs, error)) = self.pending.remove(&self.next) {
+            for (record, output) in records.iter().zip(outputs) {
+                match output {
+                    Ok(o) => self.pipeline.accept(record, o, self.writer)?,
+                    Err(e) => self.bad_rows.handle(record, e.at_record(self.name, record.position()))?
+                }
+            }
+            if let Some(e) = error {
+                return Err(CsvFiltError::from(e));
+            }
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+// Runs the records of `reader` through the pipeline's query on `threads` workers, while
+// this thread reads the input and writes the results in their original order
+pub fn filter<R : Read, W : Write>(reader:&mut Reader<R>, threads:usize, tag:Option<&str>, sink:Sink<W>) -> Result<()> {
+    let mut records = reader.byte_records();
+    let mut read_error = None;
+    let batches = iter::from_fn(move ||{
+        // the records before a bad one are still filtered, as they would be by a single thread
+        if let Some(e) = read_error.take() {
+            return Some(Err(e));
+        }
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        for res in records.by_ref().take(BATCH_SIZE) {
+            match res {
+                Ok(r) => batch.push(r),
+                Err(e) => {
+                    read_error = Some(e);
+                    break;
+                }
+            }
+        }
+        if batch.is_empty() {
+            read_error.take().map(Err)
+        }
+        else {
+            Some(Ok(Batch::Records(batch)))
+        }
+    });
+    // the records are already parsed, so no delimiter is needed
+    run(batches, b',', threads, tag, sink)
+}
+
+// Runs the records of a mapped file through the pipeline's query on `threads` workers. The
+// file, from `start` on, is cut into chunks at record boundaries and each worker parses the
+// chunks it is given, so that parsing as well as filtering is spread over the threads.
+pub fn filter_mapped<W : Write>(data:&[u8], start:Position, delimiter:u8, threads:usize, tag:Option<&str>, sink:Sink<W>) -> Result<()> {
+    run(Chunks { data, start, delimiter, size : CHUNK_SIZE }.map(Ok), delimiter, threads, tag, sink)
+}
+
+// Splits a mapped file into chunks of at least `size` bytes, or the rest of the file, that
+// end with a record. A newline ends a record unless it is inside a quoted field, and as csv
+// reads them a field is only quoted if it starts with a quote; a quote anywhere else in a
+// field is just a character of it.
+struct Chunks<'a> {
+    data : &'a [u8],
+    start : Position,
+    delimiter : u8,
+    size : usize
+}
+
+// Where in a record a byte of a chunk is
+#[derive(Clone, Copy, PartialEq)]
+enum Place {
+    FieldStart,
+    Unquoted,
+    Quoted,
+    // after a quote in a quoted field, which either ends the field or, if another follows,
+    // is an escaped quote
+    QuoteInQuoted
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Batch<'a>;
+
+    fn next(&mut self) -> Option<Batch<'a>> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let (mut end, mut lines, mut place) = (self.data.len(), 0, Place::FieldStart);
+        for (i, &b) in self.data.iter().enumerate() {
+            if b == b'\n' {
+                lines += 1;
+            }
+            place =
+                match place {
+                    Place::Quoted => if b == b'"' { Place::QuoteInQuoted } else { Place::Quoted },
+                    Place::FieldStart if b == b'"' => Place::Quoted,
+                    Place::QuoteInQuoted if b == b'"' => Place::Quoted,
+                    _ if b == self.delimiter || b == b'\n' || b == b'\r' => Place::FieldStart,
+                    _ => Place::Unquoted
+                };
+            if b == b'\n' && place == Place::FieldStart && i + 1 >= self.size {
+                end = i + 1;
+                break;
+            }
+        }
+        let (chunk, rest) = self.data.split_at(end);
+        let start = self.start.clone();
+        self.data = rest;
+        self.start.set_byte(start.byte() + end as u64).set_line(start.line() + lines);
+        Some(Batch::Chunk(chunk, start))
+    }
+}
+
+// Parses a chunk of a mapped file, giving its records their line and byte in the whole file,
+// which are what errors report
+fn read_chunk(data:&[u8], start:&Position, delimiter:u8) -> (Vec<ByteRecord>, Option<csv::Error>) {
+    let mut reader = ReaderBuilder::new().delimiter(delimiter).has_headers(false).flexible(true).from_reader(data);
+    let mut records = Vec::new();
+    for res in reader.byte_records() {
+        match res {
+            Ok(mut r) => {
+                if let Some(mut p) = r.position().cloned() {
+                    let (byte, line) = (start.byte() + p.byte(), start.line() + p.line() - 1);
+                    p.set_byte(byte).set_line(line);
+                    r.set_position(Some(p));
+                }
+                records.push(r);
+            }
+            Err(e) => return (records, Some(e))
+        }
+    }
+    (records, None)
+}
+
+// Hands `batches` out to `threads` workers, which run the query on them, and writes the
+// results in order as they come back
+fn run<'a, I, W>(mut batches:I, delimiter:u8, threads:usize, tag:Option<&str>, mut sink:Sink<W>) -> Result<()>
+    where I : Iterator<Item = csv::Result<Batch<'a>>>, W : Write
+{
+    let query = sink.pipeline.query();
+
+    let (batch_tx, batch_rx) = mpsc::sync_channel::<(usize, Batch)>(threads);
+    let batch_rx = &Mutex::new(batch_rx);
+    let (done_tx, done_rx) = mpsc::channel();
+
+    // the closure owns batch_tx, so however it returns the workers see the end of the
+    // batches and stop before the scope joins them
+    thread::scope(move |scope|{
+        for _ in 0..threads {
+            let (done_tx, query) = (done_tx.clone(), query.clone());
+            scope.spawn(move ||{
+                loop {
+                    let next = batch_rx.lock().unwrap().recv();
+                    let (seq, batch) =
+                        match next {
+                            Ok(b) => b,
+                            Err(_) => break
+                        };
+                    let (records, error) =
+                        match batch {
+                            Batch::Records(records) => (records, None),
+                            Batch::Chunk(data, start) => read_chunk(data, &start, delimiter)
+                        };
+                    let outputs = query.run_batch(&records, tag);
+                    if done_tx.send((seq, records, outputs, error)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(done_tx);
+
+        // workers only stop early by panicking, which the scope passes on once they are joined
+        let worker_gone = "a filtering thread panicked";
+        let mut read_error = None;
+        let mut sent = 0;
+
+        while !sink.pipeline.is_done() {
+            while sent - sink.next >= threads * BATCHES_IN_FLIGHT {
+                let (seq, records, outputs, error) = done_rx.recv().expect(worker_gone);
+                sink.add(seq, records, outputs, error)?;
+            }
+
+            let batch =
+                match batches.next() {
+                    Some(Ok(b)) => b,
+                    Some(Err(e)) => {
+                        read_error = Some(e);
+                        break;
+                    }
+                    None => break
+                };
+            batch_tx.send((sent, batch)).expect(worker_gone);
+            sent += 1;
+
+            while let Ok((seq, records, outputs, error)) = done_rx.try_recv() {
+                sink.add(seq, records, outputs, error)?;
+            }
+        }
+        drop(batch_tx);
+
+        while sink.next < sent {
+            let (seq, records, outputs, error) = done_rx.recv().expect(worker_gone);
+            sink.add(seq, records, outputs, error)?;
+        }
+
+        match read_error {
+            Some(e) => Err(CsvFiltError::from(e)),
+            None => Ok(())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{Reader, Writer};
+
+    use csvfilt::{Pipeline, Precedence, Schema};
+
+    use parallel::{filter, read_chunk, run, Batch, Chunks, Sink, BATCH_SIZE, CHUNK_SIZE};
+    use rejects::{BadRows, OnError};
+
+    // How the records of a run are read
+    enum Mode {
+        Single,
+        Threads,
+        // mapped, in chunks of this many bytes
+        Mapped(usize)
+    }
+
+    fn input(rows:usize) -> String {
+        let mut input = String::from("stock[string],size[int]\n");
+        for i in 0..rows {
+            // every thousandth row is bad and skipped
+            let size = if i % 1000 == 999 { "n/a".to_owned() } else { (i % 500).to_string() };
+            // and some stocks are quoted over two lines, which chunks mustn't be split inside,
+            // after one with a quote that doesn't start the field, so is just a character
+            let stock =
+                if i % 7 == 0 { format!("\"S{}\n\"\"x\"\"\"", i) }
+                else if i == 1 { "S\"1".to_owned() }
+                else { format!("S{}", i) };
+            input.push_str(&format!("{},{}\n", stock, size));
+        }
+        input
+    }
+
+    fn filter_input(input:&str, q:&str, mode:Mode) -> String {
+        let mut reader = Reader::from_reader(input.as_bytes());
+        let schema = Schema::from_header(reader.headers().unwrap()).unwrap();
+        let mut pipeline = Pipeline::new(csvfilt::parse_statement(q, &schema, Precedence::Standard).unwrap());
+        let mut bad_rows = BadRows::new(OnError::Skip, b',').unwrap();
+        let mut w = Writer::from_writer(Vec::new());
+
+        match mode {
+            Mode::Single => {
+                for r in reader.byte_records() {
+                    let r = r.unwrap();
+                    if let Err(e) = pipeline.push(&r, None, &mut w) {
+                        bad_rows.handle(&r, e).unwrap();
+                    }
+                }
+            }
+            Mode::Threads => {
+                filter(&mut reader, 3, None, Sink::new(&mut pipeline, &mut bad_rows, &mut w, "input")).unwrap();
+            }
+            Mode::Mapped(size) => {
+                let start = reader.position().clone();
+                let chunks = Chunks { data : &input.as_bytes()[start.byte() as usize..], start, delimiter : b',', size };
+                run(chunks.map(Ok), b',', 3, None, Sink::new(&mut pipeline, &mut bad_rows, &mut w, "input")).unwrap();
+            }
+        }
+        pipeline.finish(&mut w).unwrap();
+        String::from_utf8(w.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn threads_keep_the_input_order() {
+        let input = input(BATCH_SIZE * 5 + 7);
+        for q in &["SELECT * WHERE size > 250", "SELECT stock, size * 2 AS x WHERE size < 10 LIMIT 100", "SELECT stock ORDER BY size DESC, stock"] {
+            let single = filter_input(&input, q, Mode::Single);
+            assert!(!single.is_empty());
+            assert_eq!(single, filter_input(&input, q, Mode::Threads));
+            assert_eq!(single, filter_input(&input, q, Mode::Mapped(1000)));
+        }
+    }
+
+    #[test]
+    fn mapped_chunks_of_full_size_keep_the_input_order() {
+        let input = input(CHUNK_SIZE / 4);
+        assert!(input.len() > CHUNK_SIZE * 2);
+        let single = filter_input(&input, "SELECT * WHERE size > 250", Mode::Single);
+        assert_eq!(single, filter_input(&input, "SELECT * WHERE size > 250", Mode::Mapped(CHUNK_SIZE)));
+    }
+
+    #[test]
+    fn chunks_end_with_a_record_and_keep_file_positions() {
+        let input = input(BATCH_SIZE * 5 + 7);
+        let mut reader = Reader::from_reader(input.as_bytes());
+        reader.headers().unwrap();
+        let start = reader.position().clone();
+        let whole : Vec<_> = reader.byte_records().map(|r|{ r.unwrap() }).collect();
+
+        let mut chunked = Vec::new();
+        for batch in (Chunks { data : &input.as_bytes()[start.byte() as usize..], start, delimiter : b',', size : 100 }) {
+            match batch {
+                Batch::Chunk(data, start) => {
+                    assert!(data.ends_with(b"\n"));
+                    let (records, error) = read_chunk(data, &start, b',');
+                    assert!(error.is_none());
+                    chunked.extend(records);
+                }
+                Batch::Records(_) => panic!("expected a chunk")
+            }
+        }
+        assert_eq!(whole.len(), chunked.len());
+        for (a, b) in whole.iter().zip(chunked.iter()) {
+            assert_eq!(a, b);
+            let (a, b) = (a.position().unwrap(), b.position().unwrap());
+            assert_eq!((a.line(), a.byte()), (b.line(), b.byte()));
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use error::{CsvFiltError, Result};
+use query::did_you_mean;
+use schema::Schema;
+
+/// A list of column indices to output, in output order.
+///
+/// The spec is a comma separated list of items, each one of
+///   col            a single column
+///   from..to       every column from `from` to `to` inclusive, in header order;
+///                  either end may be left off to run to the first/last column
+///   !item          removes the column(s) of `item` from what has been selected so far
+/// If the spec starts with an exclusion, the exclusion applies to all columns.
+pub struct Projection(Vec<usize>);
+
+impl Projection {
+    pub fn all(schema:&Schema) -> Projection {
+        Projection((0..schema.len()).collect())
+    }
+
+    fn find(name:&str, schema:&Schema) -> Result<usize> {
+        schema.try_find_col(name)
+            .map(|(idx, _)|{ idx })
+            .ok_or_else(||{ CsvFiltError::Usage(format!("Could not find column '{}' to select{}", name, did_you_mean(schema.suggest(name)))) })
+    }
+
+    fn resolve_item(item:&str, schema:&Schema) -> Result<Vec<usize>> {
+        match item.find("..") {
+            None => Ok(vec!(Projection::find(item, schema)?)),
+            Some(pos) => {
+                let (from, to) = (item[..pos].trim(), item[pos + 2..].trim());
+                let from = if from.is_empty() { 0 } else { Projection::find(from, schema)? };
+                let last = ||{
+                    schema.len().checked_sub(1)
+                        .ok_or_else(||{ CsvFiltError::Usage(format!("Column range '{}' has no columns to run to", item)) })
+                };
+                let to = if to.is_empty() { last()? } else { Projection::find(to, schema)? };
+                if from > to {
+                    return Err(CsvFiltError::Usage(format!("Column range '{}' runs backwards", item)));
+                }
+                Ok((from..=to).collect())
+            }
+        }
+    }
+
+    pub fn parse(spec:&str, schema:&Schema) -> Result<Projection> {
+        let mut cols : Vec<usize> = Vec::new();
+
+        for (i, item) in spec.split(',').map(|s|{ s.trim() }).enumerate() {
+            if item.is_empty() {
+                return Err(CsvFiltError::Usage(format!("Empty column in selection '{}'", spec)));
+            }
+
+            if let Some(excluded) = item.strip_prefix('!') {
+                if i == 0 {
+                    cols = (0..schema.len()).collect();
+                }
+                let excluded = Projection::resolve_item(excluded.trim(), schema)?;
+                cols.retain(|c|{ !excluded.contains(c) });
+            }
+            else {
+                cols.extend(Projection::resolve_item(item, schema)?);
+            }
+        }
+
+        Ok(Projection(cols))
+    }
+
+    pub fn columns(&self) -> &[usize] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::StringRecord;
+    use projection::Projection;
+    use schema::Schema;
+
+    fn select(spec:&str) -> Vec<String> {
+        let row = StringRecord::from(vec!("VOD.L", "99.96", "100", "true"));
+        let s = Schema::trades();
+        let p = Projection::parse(spec, &s).unwrap();
+        p.columns().iter().map(|&idx|{ row[idx].to_owned() }).collect()
+    }
+
+    #[test]
+    fn columns_come_out_in_the_order_given() {
+        assert_eq!(vec!("100", "VOD.L"), select("size,stock"));
+        assert_eq!(vec!("VOD.L", "99.96", "100", "true"), select("stock..executed"));
+    }
+
+    #[test]
+    fn ranges_can_be_open() {
+        assert_eq!(vec!("VOD.L", "99.96"), select("..price"));
+        assert_eq!(vec!("100", "true"), select("size.."));
+    }
+
+    #[test]
+    fn exclusions_apply_to_everything_when_leading() {
+        assert_eq!(vec!("VOD.L", "99.96", "100"), select("!executed"));
+        assert_eq!(vec!("VOD.L", "true"), select("!price..size"));
+        assert_eq!(vec!("99.96", "100"), select("stock..size,!stock"));
+    }
+
+    #[test]
+    fn unknown_columns_are_errors() {
+        let s = Schema::trades();
+        assert!(Projection::parse("stock,volume", &s).is_err());
+        assert!(Projection::parse("size..stock", &s).is_err());
+        assert!(Projection::parse("stock,,size", &s).is_err());
+    }
+
+    #[test]
+    fn open_ranges_over_no_columns_are_errors() {
+        let empty = Schema::from_header(&StringRecord::new()).unwrap();
+        for spec in &["..", "!.."] {
+            let e = Projection::parse(spec, &empty).err().unwrap();
+            assert_eq!("Column range '..' has no columns to run to", e.to_string());
+        }
+    }
+}
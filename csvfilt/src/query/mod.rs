@@ -1,12 +1,24 @@
+mod parse_error;
 mod tokens;
 mod query_tree;
+mod row;
+mod select;
+
+pub use self::parse_error::{ErrorKind, ParseError};
+pub use self::select::{OutputRow, Pipeline, SelectQuery};
+pub use self::tokens::Span;
 
 use schema::ColType;
 use schema::{OpDouble, OpSingle};
 
-use self::query_tree::{Op,QueryTree};
+use self::query_tree::{Op,Operand,QueryTree,Statement};
+use self::row::{Cell, Row, RowValues};
+pub use self::query_tree::Precedence;
+
+use csv::{ByteRecord, StringRecord};
 
-use std::error::Error;
+use error::{CsvFiltError, Result};
+use value::Value;
 
 impl ColType {
 
@@ -23,7 +35,7 @@ impl ColType {
         }
     }
 
-    fn get_for_op_left_baked(&self, op : Op, left:&String) -> OpSingle
+    fn get_for_op_left_baked(&self, op : Op, left:&str) -> OpSingle
     {
         match op {
             Op::Eq => self.eq.1(left),
@@ -36,47 +48,65 @@ impl ColType {
     }
 }
 
-struct ColumnOp(Box<Fn(&Vec<String>) -> Result<bool, Box<Error>>>);
+// The end of an unknown column message, naming the column that was probably meant
+pub fn did_you_mean(suggestion:Option<&str>) -> String
+{
+    suggestion.map_or(String::new(), |c|{ format!(", did you mean '{}'?", c) })
+}
+
+// A condition on a row, which SQL's three-valued logic makes None, unknown, when it
+// compares a null
+type RowFn = Box<dyn Fn(&Row) -> Result<Option<bool>> + Send + Sync>;
+
+// Only values that aren't null are compared; a comparison with a null is unknown
+fn unless_null(a:&Value, b:&Value, op:&(dyn Fn(&Value, &Value) -> bool + Send + Sync)) -> Option<bool> {
+    if a.is_null() || b.is_null() { None } else { Some(op(a, b)) }
+}
+
+struct ColumnOp(RowFn);
 
 impl ColumnOp {
-    fn to_fn(self) -> Box<Fn(&Vec<String>) -> Result<bool, Box<Error>>>
+    fn into_fn(self) -> RowFn
     {
         self.0
     }
 
-    fn form_op(schema: &Schema, left:String, op : Op, right:String) -> Result<ColumnOp, Box<Error>>
+    fn form_op(schema: &Schema, left:Operand, op : Op, right:Operand) -> Result<ColumnOp>
     {
-        match (schema.try_find_col(&left), schema.try_find_col(&right)) {
-            (None, None) => // neither are columns, this is probably an error
-                {
-                    Err(From::from(format!("Could not find {} or {} as a column", left, right)))
-                }
-            (Some((idx_a,col_a)), Some((idx_b,col_b))) =>
+        match (left, right) {
+            (Operand::Literal(a), Operand::Literal(b)) =>
+                unreachable!("the parser rejects comparing {:?} with {:?}, as neither is a column", a, b),
+            (Operand::Column(a), Operand::Column(b)) =>
                 {
-                    use std::rc::Rc;
-                    if Rc::ptr_eq(&col_a.col_type,&col_b.col_type) {
-                        let op = col_a.col_type.get_for_op(op)?;
+                    let (a, b) = (Cell::new(a.index, schema), Cell::new(b.index, schema));
+                    // columns of one type compare as values, whatever formats they were written in
+                    if a.col_type.compares_with(&b.col_type) {
+                        let op = a.col_type.get_for_op(op)?;
                         Ok(ColumnOp(Box::new(move |row|{
-                            let a = row[idx_a].trim();
-                            let b = row[idx_b].trim();
-                            op(&a.to_owned(),&b.to_owned())
+                            Ok(unless_null(a.value(row)?, b.value(row)?, &op))
                         })))
                     }
                     else {
-                        Err(From::from(format!("Tried to compare values of {} and {} but the types don't match", left, right)))
+                        Err(CsvFiltError::TypeMismatch {
+                            op : op.to_string(),
+                            left : a.col_type.spec(false),
+                            right : b.col_type.spec(false)
+                        })
                     }
                 }
             
-            (None, Some((idx,col))) =>
+            (Operand::Literal(left), Operand::Column(c)) =>
                 {
-                    let op = col.col_type.get_for_op_left_baked(op, &left)?;
+                    let c = Cell::new(c.index, schema);
+                    let op = c.col_type.get_for_op_left_baked(op, &left)?;
                     Ok(ColumnOp(Box::new(move |row|{
-                        let b = row[idx].trim();
-                        op(&b.to_owned())
+                        let v = c.value(row)?;
+                        Ok(if v.is_null() { None } else { Some(op(v)) })
                     })))
                 }
-            (Some((idx,col)), None) =>
+            (Operand::Column(c), Operand::Literal(right)) =>
                 {
+                    let c = Cell::new(c.index, schema);
                     let alternate_op = 
                         match op { // we need to reverse comparison operators if baking the right param, as we only know how to bake the left
                             Op::Eq => Op::Eq,
@@ -86,74 +116,180 @@ impl ColumnOp {
                             Op::LEq => Op::GEq,
                             Op::GEq => Op::LEq
                         };
-                    let op_fn = col.col_type.get_for_op_left_baked(alternate_op, &right)?;
+                    let op_fn = c.col_type.get_for_op_left_baked(alternate_op, &right)?;
                     Ok(ColumnOp(Box::new(move |row|{
-                        let a = row[idx].trim();
-                        op_fn(&a.to_owned())
+                        let v = c.value(row)?;
+                        Ok(if v.is_null() { None } else { Some(op_fn(v)) })
                     })))
                 }
         }
     }
 }
 
-pub struct QueryFn (Box<Fn(&Vec<String>) -> Result<bool, Box<Error>>>);
+pub struct QueryFn (RowFn);
 
 use schema::Schema;
 
 impl QueryFn {
-    fn from_query_inner(q:QueryTree, s:&Schema) 
-        -> 
-            Result<
-                Box<
-                    Fn(&Vec<String>) -> Result<bool, Box<Error>>
-                >, 
-                Box<Error>
-                >
+    // `text` is the query the tree was parsed from, for pointing at errors
+    fn from_query_inner(q:QueryTree, s:&Schema, text:&str) 
+        -> Result<RowFn>
     {
         match q {
-            QueryTree::And {q1, q2} =>
+            QueryTree::And {q1, q2, ..} =>
+                {
+                    let a = QueryFn::from_query_inner(*q1, s, text)?;
+                    let b = QueryFn::from_query_inner(*q2, s, text)?;
+                    // false whatever the other side is, otherwise unknown if either side is
+                    Ok(Box::new(move |row|{
+                        match a(row)? {
+                            Some(false) => Ok(Some(false)),
+                            x => Ok(match (x, b(row)?) {
+                                (_, Some(false)) => Some(false),
+                                (Some(true), y) => y,
+                                _ => None
+                            })
+                        }
+                        }))
+                },
+            QueryTree::Or {q1, q2, ..} =>
                 {
-                    let a = QueryFn::from_query_inner(*q1, s)?;
-                    let b = QueryFn::from_query_inner(*q2, s)?;
+                    let a = QueryFn::from_query_inner(*q1, s, text)?;
+                    let b = QueryFn::from_query_inner(*q2, s, text)?;
+                    // true whatever the other side is, otherwise unknown if either side is
                     Ok(Box::new(move |row|{
-                        Ok((a(row)?) && (b(row)?))
+                        match a(row)? {
+                            Some(true) => Ok(Some(true)),
+                            x => Ok(match (x, b(row)?) {
+                                (_, Some(true)) => Some(true),
+                                (Some(false), y) => y,
+                                _ => None
+                            })
+                        }
                         }))
                 },
-            QueryTree::Or {q1, q2} =>
+            QueryTree::Not {q, ..} =>
                 {
-                    let a = QueryFn::from_query_inner(*q1, s)?;
-                    let b = QueryFn::from_query_inner(*q2, s)?;
+                    let f = QueryFn::from_query_inner(*q, s, text)?;
                     Ok(Box::new(move |row|{
-                        Ok((a(row)?) || (b(row)?))
+                        Ok(f(row)?.map(|b|{ !b }))
                         }))
                 },
-            QueryTree::Not {q} =>
+            QueryTree::IsNull { column, negated, .. } =>
                 {
-                    let f = QueryFn::from_query_inner(*q, s)?;
+                    let c = Cell::new(column.index, s);
                     Ok(Box::new(move |row|{
-                        Ok(!f(row)?)
+                        Ok(Some(c.value(row)?.is_null() != negated))
                         }))
                 },
-            QueryTree::Op{ left, op, right } =>
+            QueryTree::Op{ left, op, right, span } =>
                 {
-                    Ok(ColumnOp::form_op(s, left, op, right)?.to_fn())
+                    let op = ColumnOp::form_op(s, left, op, right).map_err(|e|{
+                        CsvFiltError::InQuery { query : text.to_owned(), span : Some(span), source : Box::new(e) }
+                    })?;
+                    Ok(op.into_fn())
                 }
         }
     }
 
-    fn from_query(q:QueryTree, s:&Schema) -> Result<QueryFn, Box<Error>>{
-        let inner = QueryFn::from_query_inner(q, s)?;
+    fn from_query(q:QueryTree, s:&Schema, text:&str) -> Result<QueryFn> {
+        let inner = QueryFn::from_query_inner(q, s, text)?;
         Ok(QueryFn(inner))
     } 
 
-    pub fn matches(&self, row:&Vec<String>) -> Result<bool, Box<Error>> {
-        self.0(row)
+    /// Whether `row` passes the filter. `row` should have the fields of the schema the query
+    /// was parsed against; one too short for a column the query reads is a
+    /// [`CsvFiltError::FieldCount`] error, and a cell that can't be read as its column's type
+    /// is a [`CsvFiltError::CellParse`] error. As in SQL, a row only passes if the filter is
+    /// true, not if comparing nulls left it unknown.
+    pub fn matches(&self, row:&StringRecord) -> Result<bool> {
+        self.matches_bytes(row.as_byte_record())
+    }
+
+    /// As [`QueryFn::matches`], for records read without UTF-8 validation. Only the fields the
+    /// query uses are decoded, so other fields may hold any bytes.
+    pub fn matches_bytes(&self, row:&ByteRecord) -> Result<bool> {
+        self.matches_row(&RowValues::default().row(row))
+    }
+
+    // As matches, sharing the values read from the row with the rest of the query
+    fn matches_row(&self, row:&Row) -> Result<bool> {
+        Ok(self.0(row)? == Some(true))
     }
 }
 
-pub fn parse(q:&String, s:&Schema) -> Result<QueryFn, Box<Error>>
+/// Parses a filter such as `price > 100 && !executed` and compiles it against `s`
+pub fn parse(q:&str, s:&Schema, prec:Precedence) -> Result<QueryFn>
 {
-    let query = QueryTree::from_qstring(q)?;
+    let query = QueryTree::from_qstring(q, s, prec).map_err(|e|{ CsvFiltError::in_query(q, e) })?;
 
-    QueryFn::from_query(*query, s)
-}
\ No newline at end of file
+    QueryFn::from_query(*query, s, q)
+}
+
+/// Whether `q` is a SELECT statement rather than a filter. A filter on a column called
+/// `select`, such as `select = 1`, is not one.
+pub fn is_statement(q:&str) -> bool
+{
+    Statement::is_statement(q)
+}
+
+/// Parses `SELECT <columns> [WHERE <filter>] [ORDER BY <columns>] [LIMIT <n>]` and compiles
+/// it against `s`
+pub fn parse_statement(q:&str, s:&Schema, prec:Precedence) -> Result<SelectQuery>
+{
+    let mut statement = Statement::from_qstring(q, s, prec).map_err(|e|{ CsvFiltError::in_query(q, e) })?;
+
+    let filter =
+        match statement.filter.take() {
+            Some(f) => Some(QueryFn::from_query(*f, s, q)?),
+            None => None
+        };
+
+    SelectQuery::from_statement(statement, filter, s)
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{ByteRecord, StringRecord};
+
+    use query::{is_statement, parse, Precedence};
+    use schema::Schema;
+
+    #[test]
+    fn comparisons_with_nulls_are_unknown_and_unknown_never_matches() {
+        let header = StringRecord::from(vec!("size[int?]", "price[float?]"));
+        let schema = Schema::from_header(&header).unwrap();
+        let rows = [["100", "99.5"], ["", ""], ["", "101.5"], ["300", ""]];
+        let matching = |q:&str|{
+            let f = parse(q, &schema, Precedence::Standard).unwrap();
+            (0..rows.len()).filter(|&i|{ f.matches_bytes(&ByteRecord::from(rows[i].to_vec())).unwrap() }).collect::<Vec<_>>()
+        };
+        assert_eq!(vec!(0), matching("size < 200"));
+        assert_eq!(vec!(3), matching("!(size < 200)"));
+        // false && unknown is false, and true || unknown is true
+        assert_eq!(vec!(0, 3), matching("!(size < 200 && price > 100)"));
+        assert_eq!(vec!(0, 2), matching("size < 200 || price > 100"));
+        assert_eq!(vec!(1, 2), matching("size IS NULL"));
+        assert_eq!(vec!(0, 3), matching("size IS NOT NULL"));
+    }
+
+    #[test]
+    fn short_records_are_field_count_errors() {
+        let header = StringRecord::from(vec!("stock[string]", "price[float]", "size[int]"));
+        let schema = Schema::from_header(&header).unwrap();
+        let f = parse("size > 100", &schema, Precedence::Standard).unwrap();
+        let e = f.matches(&StringRecord::from(vec!("VOD.L", "99.5"))).err().unwrap();
+        assert_eq!("Expected 3 fields, found 2", e.to_string());
+        assert!(f.matches(&StringRecord::from(vec!("VOD.L", "99.5", "200"))).unwrap());
+    }
+
+    #[test]
+    fn a_column_called_select_can_be_filtered_on() {
+        let schema = Schema::from_header(&StringRecord::from(vec!("select[int]", "stock[string]"))).unwrap();
+        let q = "select = 1";
+        assert!(!is_statement(q));
+        let f = parse(q, &schema, Precedence::Standard).unwrap();
+        assert!(f.matches(&StringRecord::from(vec!("1", "VOD.L"))).unwrap());
+        assert!(!f.matches(&StringRecord::from(vec!("2", "VOD.L"))).unwrap());
+    }
+}
@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::fmt;
+
+use error::CsvFiltError;
+use query::did_you_mean;
+use query::tokens::{Span, SpannedToken, Token};
+
+/// Everything that can go wrong turning query text into something to run
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    /// the text can't be split into tokens, e.g. an unterminated string
+    Tokenise(String),
+    /// `expected` describes what the grammar wanted, `found` is None at the end of the query
+    Unexpected { expected : String, found : Option<Token> },
+    // a complete query followed by more tokens
+    Trailing(Token),
+    UnknownColumn { name : String, suggestion : Option<String> },
+    /// a comparison of two values, neither of which is a column
+    NoColumn { left : String, right : String, suggestion : Option<String> },
+    /// the 1-based position of a computed SELECT item without AS
+    MissingAlias(usize)
+}
+
+/// An error and where in the query it is, a span of None meaning the end of the query
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub kind : ErrorKind,
+    pub span : Option<Span>
+}
+
+impl ParseError {
+    pub fn new(kind:ErrorKind, span:Option<Span>) -> ParseError {
+        ParseError { kind, span }
+    }
+
+    pub fn expected(expected:&str, found:Option<&&SpannedToken>) -> ParseError {
+        ParseError {
+            kind : ErrorKind::Unexpected { expected : expected.to_owned(), found : found.map(|t|{ t.token.clone() }) },
+            span : found.map(|t|{ t.span })
+        }
+    }
+
+    pub fn into_error(self) -> CsvFiltError {
+        match self.kind {
+            ErrorKind::Tokenise(s) => CsvFiltError::Tokenise(s),
+            _ => CsvFiltError::Parse(self)
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Tokenise(ref s) => write!(f, "{}", s),
+            ErrorKind::Unexpected { ref expected, found : None } => write!(f, "Expected {}, found <EOL>", expected),
+            ErrorKind::Unexpected { ref expected, found : Some(ref t) } => write!(f, "Expected {}, found {}", expected, t),
+            ErrorKind::Trailing(ref t) => write!(f, "Unexpected {} after the end of the query", t),
+            ErrorKind::UnknownColumn { ref name, ref suggestion } =>
+                write!(f, "Could not find column '{}'{}", name, did_you_mean(suggestion.as_ref().map(|s|{ s.as_str() }))),
+            ErrorKind::NoColumn { ref left, ref right, ref suggestion } =>
+                write!(f, "Could not find {:?} or {:?} as a column{}", left, right, did_you_mean(suggestion.as_ref().map(|s|{ s.as_str() }))),
+            ErrorKind::MissingAlias(n) =>
+                write!(f, "Computed column {} of the SELECT needs a name, given with AS", n)
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use csv::StringRecord;
+    use query::{parse, Precedence};
+    use schema::Schema;
+
+    fn error(q:&str) -> String {
+        let header = StringRecord::from(vec!("stock[string]", "price[float]", "size[int]"));
+        let schema = Schema::from_header(&header).unwrap();
+        parse(q, &schema, Precedence::Standard).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn errors_point_at_the_query() {
+        assert_eq!("Expected op, found '('\n  price ( 100\n        ^", error("price ( 100"));
+        assert_eq!("Expected a column or value, found <EOL>\n  size > 1 && price >\n                     ^", error("size > 1 && price >"));
+        assert_eq!("Unterminated string literal, expected closing '\n  stock = 'VOD.L\n          ^^^^^^", error("stock = 'VOD.L"));
+    }
+
+    #[test]
+    fn misspelt_columns_are_suggested() {
+        assert_eq!(
+            "Could not find column 'Prise', did you mean 'price'?\n  size > 1 && $Prise > 2\n              ^^^^^^",
+            error("size > 1 && $Prise > 2"));
+        assert_eq!(
+            "Could not find \"stok\" or \"VOD.L\" as a column, did you mean 'stock'?\n  stok = VOD.L\n  ^^^^",
+            error("stok = VOD.L"));
+        assert_eq!(
+            "Could not find \"pirce\" or \"1\" as a column, did you mean 'price'?\n  pirce > 1\n  ^^^^^",
+            error("pirce > 1"));
+        assert_eq!(
+            "Could not find \"VOD.L\" or \"stcok\" as a column, did you mean 'stock'?\n  size > 1 && VOD.L = stcok\n                      ^^^^^",
+            error("size > 1 && VOD.L = stcok"));
+        assert_eq!(
+            "Could not find \"volume\" or \"1\" as a column\n  volume > 1\n  ^^^^^^^^^^",
+            error("volume > 1"));
+        assert_eq!("Could not find column 'volume'\n  $volume > 1\n  ^^^^^^^", error("$volume > 1"));
+    }
+}
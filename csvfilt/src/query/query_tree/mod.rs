@@ -1,4 +1,10 @@
 mod parsing;
+mod statement;
+
+use std::fmt;
+
+use query::parse_error::{ErrorKind, ParseError};
+use schema::Schema;
 
 #[derive(Debug,PartialEq)]
 pub enum Op {
@@ -10,44 +16,226 @@ pub enum Op {
     GEq
 }
 
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol =
+            match *self {
+                Op::Eq => "=",
+                Op::NotEq => "!=",
+                Op::Lt => "<",
+                Op::LEq => "<=",
+                Op::Gt => ">",
+                Op::GEq => ">="
+            };
+        write!(f, "{}", symbol)
+    }
+}
+
+// A column of the schema, resolved when the query is parsed
+#[derive(Debug,PartialEq,Clone)]
+pub struct ColumnRef {
+    pub name : String,
+    pub index : usize
+}
+
+impl ColumnRef {
+    fn find(name:&str, s:&Schema) -> Option<ColumnRef> {
+        s.try_find_col(name).map(|(index, _)|{ ColumnRef { name : name.to_owned(), index } })
+    }
+
+    // An explicit `col` or $col reference, which must name a column
+    fn resolve(name:&str, span:Span, s:&Schema) -> Result<ColumnRef, ParseError> {
+        ColumnRef::find(name, s).ok_or_else(||{
+            let kind = ErrorKind::UnknownColumn { name : name.to_owned(), suggestion : s.suggest(name).map(|c|{ c.to_owned() }) };
+            ParseError::new(kind, Some(span))
+        })
+    }
+}
+
+// One side of a comparison. A bare word is a column if the schema has one of that name and
+// a constant otherwise; a quoted literal is always a constant.
+#[derive(Debug,PartialEq)]
+pub enum Operand {
+    Column(ColumnRef),
+    Literal(String)
+}
+
+impl Operand {
+    fn from_word(word:&str, s:&Schema) -> Operand {
+        match ColumnRef::find(word, s) {
+            Some(c) => Operand::Column(c),
+            None => Operand::Literal(word.to_owned())
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Column(ref c) => write!(f, "{}", c.name),
+            Operand::Literal(ref s) => write!(f, "{:?}", s)
+        }
+    }
+}
+
+/// How && and || group without brackets. Standard gives && the higher precedence and makes
+/// both left associative; Legacy is the original behaviour, where both have the same
+/// precedence and group to the right, so `a && b || c` means `a && (b || c)`.
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Precedence {
+    Standard,
+    Legacy
+}
+
+// Each node keeps the span of query text it was parsed from, so errors found when the
+// query is compiled against the schema can point at it
 #[derive(Debug,PartialEq)]
 pub enum QueryTree {
     Op {
-        left : String,
+        left : Operand,
         op : Op,
-        right : String
+        right : Operand,
+        span : Span
+    },
+    // `column IS NULL`, or IS NOT NULL if negated
+    IsNull {
+        column : ColumnRef,
+        negated : bool,
+        span : Span
     },
     Not {
-        q : Box<QueryTree>
+        q : Box<QueryTree>,
+        span : Span
     },
     And {
         q1 : Box<QueryTree>,
-        q2 : Box<QueryTree>
+        q2 : Box<QueryTree>,
+        span : Span
     },
     Or {
         q1 : Box<QueryTree>,
-        q2 : Box<QueryTree>
+        q2 : Box<QueryTree>,
+        span : Span
     }
 }
 
-use std::error::{Error};
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div
+}
 
-use query::tokens::{Token, tokenise};
+impl fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol =
+            match *self {
+                ArithOp::Add => "+",
+                ArithOp::Sub => "-",
+                ArithOp::Mul => "*",
+                ArithOp::Div => "/"
+            };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Debug,PartialEq)]
+pub enum Expr {
+    Column(ColumnRef),
+    Const(String), // a bare word that isn't a column, e.g. a number
+    Literal(String),
+    Arith {
+        left : Box<Expr>,
+        op : ArithOp,
+        right : Box<Expr>
+    }
+}
+
+#[derive(Debug,PartialEq)]
+pub enum SelectItem {
+    All,
+    Expr {
+        expr : Expr,
+        alias : Option<String>
+    }
+}
+
+#[derive(Debug,PartialEq)]
+pub struct OrderKey {
+    pub name : String,
+    pub descending : bool
+}
+
+// SELECT <select> [WHERE <filter>] [ORDER BY <order_by>] [LIMIT <limit>]
+#[derive(Debug,PartialEq)]
+pub struct Statement {
+    pub select : Vec<SelectItem>,
+    pub filter : Option<Box<QueryTree>>,
+    pub order_by : Vec<OrderKey>,
+    pub limit : Option<usize>
+}
+
+use query::tokens::{peek, SpannedToken, Span, tokenise};
+use self::parsing::{is_compared, is_keyword};
 
 impl QueryTree {
 
-    fn from_tokens(tokens: Vec<Token>) -> Result<Box<QueryTree>, Box<Error>> {
+    pub fn span(&self) -> Span {
+        match *self {
+            QueryTree::Op { span, .. } | QueryTree::IsNull { span, .. } | QueryTree::Not { span, .. } |
+                QueryTree::And { span, .. } | QueryTree::Or { span, .. } => span
+        }
+    }
+
+    // The same tree with every span zeroed, so tests can compare structure alone
+    #[cfg(test)]
+    pub fn without_spans(self) -> Box<QueryTree> {
+        let span = Span::default();
+        Box::new(match self {
+            QueryTree::Op { left, op, right, .. } => QueryTree::Op { left, op, right, span },
+            QueryTree::IsNull { column, negated, .. } => QueryTree::IsNull { column, negated, span },
+            QueryTree::Not { q, .. } => QueryTree::Not { q : q.without_spans(), span },
+            QueryTree::And { q1, q2, .. } => QueryTree::And { q1 : q1.without_spans(), q2 : q2.without_spans(), span },
+            QueryTree::Or { q1, q2, .. } => QueryTree::Or { q1 : q1.without_spans(), q2 : q2.without_spans(), span }
+        })
+    }
+
+    fn from_tokens(tokens: Vec<SpannedToken>, schema:&Schema, prec:Precedence) -> Result<Box<QueryTree>, ParseError> {
         let mut peekable = tokens.iter().peekable();
-        parsing::entry(&mut peekable)
+        parsing::entry(&mut peekable, schema, prec)
     }
 
 
-    pub fn from_qstring(s:&String) -> Result<Box<QueryTree>, Box<Error>>
+    pub fn from_qstring(s:&str, schema:&Schema, prec:Precedence) -> Result<Box<QueryTree>, ParseError>
     {
-        // TODO
-        //QueryTree::form_op(s, "stock".to_owned(), Op::Eq, "VOD.L".to_owned())?;
         let tokens = tokenise(s)?;
 
-        QueryTree::from_tokens(tokens)
+        QueryTree::from_tokens(tokens, schema, prec)
+    }
+}
+impl Statement {
+    // Whether the query starts with SELECT, other than as the column of a filter such as
+    // `select = 1` or `select IS NULL`. A query that can't be tokenised goes by its first
+    // word, so a broken SELECT is reported as one.
+    pub fn is_statement(s:&str) -> bool
+    {
+        let tokens =
+            match tokenise(s) {
+                Ok(tokens) => tokens,
+                Err(_) => return s.split_whitespace().next().is_some_and(|w|{ w.eq_ignore_ascii_case("select") })
+            };
+        let mut p = tokens.iter().peekable();
+        if !is_keyword(peek(&mut p), "SELECT") {
+            return false;
+        }
+        !is_compared(&p)
     }
-}
\ No newline at end of file
+
+    pub fn from_qstring(s:&str, schema:&Schema, prec:Precedence) -> Result<Statement, ParseError>
+    {
+        let tokens = tokenise(s)?;
+        let mut peekable = tokens.iter().peekable();
+        statement::entry(&mut peekable, schema, prec)
+    }
+}
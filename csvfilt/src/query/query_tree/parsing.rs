@@ -1,176 +1,298 @@
+use query::parse_error::{ErrorKind, ParseError};
+use query::tokens::{negative_number, peek, Span, Token, TokenStream};
+use query::query_tree::{ColumnRef, QueryTree, Op, Operand, Precedence};
+use schema::Schema;
+
+// query := S
+// S := or
+// or := and | or || and | or OR and
+// and := expr | and && expr | and AND expr
+// expr := binop | not | bracketed
+// not := !expr | NOT expr
+// bracketed := (S)
+// binop := operand op operand | column IS NULL | column IS NOT NULL
+// operand := ident | literal | column | -number
+// column := `any text` | $ident
+// op := < | > | <= | >= | = | !=
+//
+// With Precedence::Legacy the S, or and and rules are instead
+// S := expr | expr && S | expr || S
+//
+// IS, NULL, NOT, AND and OR are case insensitive, like the keywords of a SELECT statement.
+// They are bare words rather than tokens of their own, so they are only keywords where one
+// can appear, and `not = 1` and `x = 1 AND or = 2` compare columns called not and or.
+
+type ParseResult<T> = Result<T, ParseError>;
+
+// Keywords are bare words rather than tokens of their own, so columns can have their names
+pub fn is_keyword(tok : Option<&Token>, kw : &str) -> bool
+{
+    match tok {
+        Some(Token::ConstOrIdentifier(s)) => s.eq_ignore_ascii_case(kw),
+        _ => false
+    }
+}
 
-use std::iter::Peekable;
-use std::slice::Iter;
+fn is_and(tok : Option<&Token>) -> bool
+{
+    tok == Some(&Token::And) || is_keyword(tok, "AND")
+}
 
-use std::error::Error;
+fn is_or(tok : Option<&Token>) -> bool
+{
+    tok == Some(&Token::Or) || is_keyword(tok, "OR")
+}
 
-use query::tokens::Token;
-use query::query_tree::{QueryTree, Op};
+// NOT starts an expression, as a column called not does, so it is only a keyword if it isn't
+// compared with anything
+fn is_not(p : &TokenStream) -> bool
+{
+    let mut p = p.clone();
+    let tok = peek(&mut p);
+    tok == Some(&Token::Not) || (is_keyword(tok, "NOT") && !is_compared(&p))
+}
 
-// S := expr | and | or
-// and := expr && S
-// or := expr || S
-// expr := binop | not | bracketed
-// not := !backeted
-// bracketed := (S)
-// binop := ident op ident
-// op := < | > | <= | >= | = | !=
+// Whether the next token, a bare word, is followed by a comparison, as in `select = 1` or
+// `not IS NULL`, so is the name of a column rather than a keyword
+pub fn is_compared(p : &TokenStream) -> bool
+{
+    let mut ahead = p.clone();
+    ahead.next();
+    match peek(&mut ahead) {
+        Some(&Token::Eq) | Some(&Token::NotEq) | Some(&Token::Lt) | Some(&Token::LEq) | Some(&Token::Gt) | Some(&Token::GEq) => true,
+        next if is_keyword(next, "IS") => {
+            ahead.next();
+            let next = peek(&mut ahead);
+            is_keyword(next, "NULL") || is_keyword(next, "NOT") || next == Some(&Token::Not)
+        }
+        _ => false
+    }
+}
 
-pub fn entry(p : &mut Peekable<Iter<Token>>) -> Result<Box<QueryTree>, Box<Error>>
+pub fn keyword(kw : &str, p : &mut TokenStream) -> Result<(), ParseError>
 {
-    maybe_and_or(p)
+    if is_keyword(peek(p), kw) {
+        p.next().unwrap();
+        Ok(())
+    }
+    else {
+        Err(ParseError::expected(kw, p.peek()))
+    }
+}
+
+// A whole query, nothing may follow it
+pub fn entry(p : &mut TokenStream, s : &Schema, prec : Precedence) -> ParseResult<Box<QueryTree>>
+{
+    let q = condition(p, s, prec)?;
+    match p.next() {
+        None => Ok(q),
+        Some(tok) => Err(ParseError::new(ErrorKind::Trailing(tok.token.clone()), Some(tok.span)))
+    }
+}
+
+// S, which may be followed by more tokens, e.g. the rest of a SELECT statement
+pub fn condition(p : &mut TokenStream, s : &Schema, prec : Precedence) -> ParseResult<Box<QueryTree>>
+{
+    match prec {
+        Precedence::Standard => or(p, s, prec),
+        Precedence::Legacy => maybe_and_or(p, s, prec)
+    }
 }    
 
+fn or(p : &mut TokenStream, s : &Schema, prec : Precedence) -> ParseResult<Box<QueryTree>>
+{
+    let mut current = and(p, s, prec)?;
+    while is_or(peek(p)) {
+        p.next().unwrap();
+        let other = and(p, s, prec)?;
+        let span = current.span().to(other.span());
+        current = Box::new(QueryTree::Or { q1 : current, q2 : other, span });
+    }
+    Ok(current)
+}
 
-pub fn maybe_and_or(p : &mut Peekable<Iter<Token>>) -> Result<Box<QueryTree>, Box<Error>>
+fn and(p : &mut TokenStream, s : &Schema, prec : Precedence) -> ParseResult<Box<QueryTree>>
 {
-    let current = expr(p)?;
-    match p.peek() {
-        None => Ok(current),
-        Some (&tok) => match tok {
-            &Token::And => 
-                {
-                    p.next().unwrap();
-                    let other = entry(p)?;
-                    Ok(Box::new(
-                        QueryTree::And { q1 : current, q2 : other }
-                    ))
-                }
-            &Token::Or =>
-                {
-                    p.next().unwrap();
-                    let other = entry(p)?;
-                    Ok(Box::new(
-                        QueryTree::Or { q1 : current, q2 : other }
-                    ))
-                }
-            _ =>
-                Ok(current)
-        }
+    let mut current = expr(p, s, prec)?;
+    while is_and(peek(p)) {
+        p.next().unwrap();
+        let other = expr(p, s, prec)?;
+        let span = current.span().to(other.span());
+        current = Box::new(QueryTree::And { q1 : current, q2 : other, span });
     }
+    Ok(current)
 }
 
-pub fn bracketed(check_open:bool, p : &mut Peekable<Iter<Token>>) -> Result<Box<QueryTree>, Box<Error>>
+
+pub fn maybe_and_or(p : &mut TokenStream, s : &Schema, prec : Precedence) -> ParseResult<Box<QueryTree>>
 {
-    if check_open
-    {
-        match p.peek()
-        {
-            None =>
-                return Err(From::from("Expected '(', found <EOL>")),
-            Some(&tok) => match tok {
-                &Token::OpenBracket =>
-                    {
-                        p.next().unwrap();
-                    }
-                t => 
-                    return Err(From::from(format!("Expected '(', found {:?}", t)))
-                    
-            }
-        }
+    let current = expr(p, s, prec)?;
+    if is_and(peek(p)) {
+        p.next().unwrap();
+        let other = condition(p, s, prec)?;
+        let span = current.span().to(other.span());
+        Ok(Box::new(
+            QueryTree::And { q1 : current, q2 : other, span }
+        ))
+    }
+    else if is_or(peek(p)) {
+        p.next().unwrap();
+        let other = condition(p, s, prec)?;
+        let span = current.span().to(other.span());
+        Ok(Box::new(
+            QueryTree::Or { q1 : current, q2 : other, span }
+        ))
+    }
+    else {
+        Ok(current)
     }
+}
 
-    let inner = entry(p)?;
+// The opening bracket has already been consumed
+pub fn bracketed(p : &mut TokenStream, s : &Schema, prec : Precedence) -> ParseResult<Box<QueryTree>>
+{
+    let inner = condition(p, s, prec)?;
 
-    match p.peek()
+    match peek(p)
     {
-        None =>
-            return Err(From::from("Expected ')', found <EOL>")),
-        Some(&tok) => match tok {
-            &Token::CloseBracket =>
-                {
-                    p.next().unwrap();
-                    Ok(inner)
-                }
-            t => 
-                return Err(From::from(format!("Expected ')', found {:?}", t)))
-                
-        }
+        Some(&Token::CloseBracket) =>
+            {
+                p.next().unwrap();
+                Ok(inner)
+            }
+        _ => 
+            Err(ParseError::expected("')'", p.peek()))
     }
 }
 
-pub fn expr(p : &mut Peekable<Iter<Token>>) -> Result<Box<QueryTree>, Box<Error>>
+pub fn expr(p : &mut TokenStream, s : &Schema, prec : Precedence) -> ParseResult<Box<QueryTree>>
 {
-    match p.peek() {
-        None => Err(From::from("Expected expr, got <EOL>")),
-        Some (&tok) =>
-            match tok {
-                &Token::ConstOrIdentifier(ref nm) =>
-                    {
-                        p.next().unwrap();
-                        binop(nm.clone(), p)
-                    }
-                &Token::Not =>
-                    {
-                        p.next().unwrap();
-                        let inner_q = bracketed(true,p)?;
-                        Ok(Box::new(QueryTree::Not { q : inner_q }))
-                    }
-                &Token::OpenBracket =>
-                    {
-                        p.next().unwrap();
-                        bracketed(false, p)
-                    }
-                _ =>
-                    unimplemented!()
+    if is_not(p) {
+        let not = p.next().unwrap().span;
+        let inner_q = expr(p, s, prec)?;
+        let span = not.to(inner_q.span());
+        return Ok(Box::new(QueryTree::Not { q : inner_q, span }));
+    }
+    match peek(p) {
+        Some(Token::ConstOrIdentifier(_)) | Some(Token::Literal(_)) | Some(Token::Column(_)) | Some(&Token::Minus) =>
+            {
+                let left = operand(p, s)?;
+                binop(left, p, s)
+            }
+        Some(&Token::OpenBracket) =>
+            {
+                p.next().unwrap();
+                bracketed(p, s, prec)
             }
+        _ =>
+            Err(ParseError::expected("a comparison, '!' or '('", p.peek()))
     }
 }
 
-fn op (p : &mut Peekable<Iter<Token>>) -> Result<Op, Box<Error>>
+fn op (p : &mut TokenStream) -> ParseResult<Op>
 {
-    match p.next() {
-        None => Err(From::from("Expected op, got <EOL>")),
-        Some (ref tok) =>  match tok {
-            &&Token::Eq => Ok(Op::Eq),
-            &&Token::NotEq => Ok(Op::NotEq),
-            &&Token::Lt => Ok(Op::Lt),
-            &&Token::Gt => Ok(Op::Gt),
-            &&Token::GEq => Ok(Op::GEq),
-            &&Token::LEq => Ok(Op::LEq),
-            x => Err(From::from(format!("Expected op, got {:?}", x)))
-        }
+    let tok = p.next();
+    match tok.map(|t|{ &t.token }) {
+        Some (&Token::Eq) => Ok(Op::Eq),
+        Some (&Token::NotEq) => Ok(Op::NotEq),
+        Some (&Token::Lt) => Ok(Op::Lt),
+        Some (&Token::Gt) => Ok(Op::Gt),
+        Some (&Token::GEq) => Ok(Op::GEq),
+        Some (&Token::LEq) => Ok(Op::LEq),
+        _ => Err(ParseError::expected("op", tok.as_ref()))
     }
 }
 
-fn ident (p : &mut Peekable<Iter<Token>>) -> Result<String, Box<Error>>
+fn operand (p : &mut TokenStream, s : &Schema) -> ParseResult<(Operand, Span)>
 {
-    match p.peek() {
-        None => Err(From::from("Expected ident, got <EOL>")),
-        Some (&tok) =>  match tok {
-            &Token::ConstOrIdentifier(ref id) => 
-                {
-                    p.next().unwrap();
-                    Ok(id.clone())
-                }
-            x => Err(From::from(format!("Expected ident, got {:?}", *x)))
+    if let Some((number, span)) = negative_number(p) {
+        return Ok((Operand::Literal(number), span));
+    }
+    let tok = p.next();
+    match tok {
+        Some(t) => {
+            let operand =
+                match t.token {
+                    Token::ConstOrIdentifier(ref id) => Operand::from_word(id, s),
+                    Token::Literal(ref lit) => Operand::Literal(lit.clone()),
+                    Token::Column(ref name) => Operand::Column(ColumnRef::resolve(name, t.span, s)?),
+                    _ => return Err(ParseError::expected("a column or value", tok.as_ref()))
+                };
+            Ok((operand, t.span))
         }
+        None => Err(ParseError::expected("a column or value", None))
     }
 }
 
-fn binop(left : String, p : &mut Peekable<Iter<Token>>) -> Result<Box<QueryTree>, Box<Error>>
+fn binop(left : (Operand, Span), p : &mut TokenStream, s : &Schema) -> ParseResult<Box<QueryTree>>
 {
+    if is_keyword(peek(p), "IS") {
+        return is_null(left, p, s);
+    }
     let operation = op(p)?;
-    let right = ident(p)?;
+    let right = operand(p, s)?;
+    if let (Operand::Literal(a), Operand::Literal(b)) = (&left.0, &right.0) {
+        return Err(no_column((a, left.1), (b, right.1), s));
+    }
 
     Ok(Box::new(
-        QueryTree::Op {left : left, op : operation, right : right}
+        QueryTree::Op {left : left.0, op : operation, right : right.0, span : left.1.to(right.1)}
     ))
 }
 
+// A comparison of two values, neither of which is a column, which is probably a misspelt
+// column. The error points at whichever looks like one, or at the whole comparison.
+fn no_column(left : (&str, Span), right : (&str, Span), s : &Schema) -> ParseError
+{
+    let (suggestion, span) =
+        match (s.suggest(left.0), s.suggest(right.0)) {
+            (Some(c), _) => (Some(c.to_owned()), left.1),
+            (None, Some(c)) => (Some(c.to_owned()), right.1),
+            (None, None) => (None, left.1.to(right.1))
+        };
+    ParseError::new(ErrorKind::NoColumn { left : left.0.to_owned(), right : right.0.to_owned(), suggestion }, Some(span))
+}
+
+// IS [NOT] NULL after `left`, which has to be a column
+fn is_null(left : (Operand, Span), p : &mut TokenStream, s : &Schema) -> ParseResult<Box<QueryTree>>
+{
+    keyword("IS", p)?;
+    let negated = peek(p) == Some(&Token::Not) || is_keyword(peek(p), "NOT");
+    if negated {
+        p.next().unwrap();
+    }
+    let end = p.peek().map(|t|{ t.span });
+    keyword("NULL", p)?;
+
+    let column =
+        match left.0 {
+            Operand::Column(c) => c,
+            Operand::Literal(name) => {
+                let suggestion = s.suggest(&name).map(|c|{ c.to_owned() });
+                return Err(ParseError::new(ErrorKind::UnknownColumn { name, suggestion }, Some(left.1)));
+            }
+        };
+    let span = left.1.to(end.unwrap());
+    Ok(Box::new(QueryTree::IsNull { column, negated, span }))
+}
+
 
+#[cfg(test)]
 mod tests {
-    use query::tokens::Token;
+    use query::parse_error::ErrorKind;
+    use query::tokens::{Span, SpannedToken, Token};
     use query::query_tree::Op;
-    use std::error::Error;
+
+    const NO_SPAN : Span = Span { start : 0, end : 0 };
 
     #[test] 
     fn exhaustive_op_parsing() {
-        fn fail_on(tok : Token) -> (Token, Result<Op, Box<Error>>)
+        type OpResult = Result<Op, ErrorKind>;
+        fn fail_on(tok : Token) -> (Token, OpResult)
         {
-            (tok.clone(), Err(From::from(format!("Expected op, got {:?}", tok))))
+            (tok.clone(), Err(ErrorKind::Unexpected { expected : "op".to_owned(), found : Some(tok) }))
         }
-        let ops : [(Token, Result<Op, Box<Error>>);12] =
+        let ops : [(Token, OpResult);19] =
                 [
                     (Token::Eq , Ok(Op::Eq)),
                     (Token::NotEq , Ok(Op::NotEq)),
@@ -181,36 +303,41 @@ mod tests {
                     fail_on(Token::OpenBracket),
                     fail_on(Token::CloseBracket),
                     fail_on(Token::ConstOrIdentifier("a".to_owned())),
+                    fail_on(Token::Literal("a".to_owned())),
+                    fail_on(Token::Column("a".to_owned())),
                     fail_on(Token::Not),
                     fail_on(Token::And),
-                    fail_on(Token::Or)
+                    fail_on(Token::Or),
+                    fail_on(Token::Comma),
+                    fail_on(Token::Star),
+                    fail_on(Token::Plus),
+                    fail_on(Token::Minus),
+                    fail_on(Token::Slash)
                 ];
 
         use query::query_tree::parsing::op;
 
-        use std::iter::Peekable;
-
-        for &(ref x,ref expected) in ops.iter() {
-            let x_copy = x.clone();
-            let v = vec!(x_copy);
+        for (x,expected) in ops.iter() {
+            let v = [SpannedToken { token : x.clone(), span : NO_SPAN }];
             let mut stream = v.iter().peekable();
-            let actual = op(&mut stream);
-            match (expected, actual) {
-                (&Err(ref x), Err(ref y)) => {
-                    let str_x = format!("{}", x);
-                    let str_y = format!("{}", y);
-                    assert_eq!(str_x, str_y)
-                },
-                (&Ok(_), Err(_)) => panic!("doesn't match"),
-                (&Err(_), Ok(_)) => panic!("doesn't match"),
-                (&Ok(ref x), Ok(ref y)) => assert_eq!(*x, *y),
-            }
+            let actual = op(&mut stream).map_err(|e|{ e.kind });
+            assert_eq!(*expected, actual);
         }
     }
 
     use query::tokens::tokenise;
 
-    use query::query_tree::QueryTree;
+    use query::query_tree::{ColumnRef, QueryTree, Operand, Precedence};
+    use schema::Schema;
+
+    fn schema() -> Schema {
+        Schema::of(&["foo[bool]", "bar[bool]", "baz[bool]", "stock[string]", "name[string]"])
+    }
+
+    fn col(name:&str) -> Operand {
+        let index = schema().try_find_col(name).unwrap().0;
+        Operand::Column(ColumnRef { name : name.to_owned(), index })
+    }
 
     #[test]
     fn and_works()
@@ -219,16 +346,16 @@ mod tests {
         let tokens = tokenise(&test).unwrap();
 
         let expected = 
-            Box::new(QueryTree::And {
-                q1 : Box::new(QueryTree::Op {
-                    left : "foo".to_owned(),
+            Box::new(QueryTree::And { span : NO_SPAN,
+                q1 : Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : col("foo"),
                     op : Op::Eq,
-                    right : "true".to_owned(),
+                    right : Operand::Literal("true".to_owned()),
                 }),
-                q2 : Box::new(QueryTree::Op {
-                    left : "bar".to_owned(),
+                q2 : Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : col("bar"),
                     op : Op::Eq,
-                    right : "false".to_owned(),
+                    right : Operand::Literal("false".to_owned()),
                 }),
             });
 
@@ -236,7 +363,7 @@ mod tests {
         
         let mut stream = tokens.iter().peekable();
         
-        let actual = entry(&mut stream).unwrap();
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap().without_spans();
 
         assert_eq!(expected, actual);
     }
@@ -248,16 +375,16 @@ mod tests {
         let tokens = tokenise(&test).unwrap();
 
         let expected = 
-            Box::new(QueryTree::Or {
-                q1 : Box::new(QueryTree::Op {
-                    left : "foo".to_owned(),
+            Box::new(QueryTree::Or { span : NO_SPAN,
+                q1 : Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : col("foo"),
                     op : Op::Eq,
-                    right : "true".to_owned(),
+                    right : Operand::Literal("true".to_owned()),
                 }),
-                q2 : Box::new(QueryTree::Op {
-                    left : "bar".to_owned(),
+                q2 : Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : col("bar"),
                     op : Op::Eq,
-                    right : "false".to_owned(),
+                    right : Operand::Literal("false".to_owned()),
                 }),
             });
 
@@ -265,7 +392,7 @@ mod tests {
         
         let mut stream = tokens.iter().peekable();
         
-        let actual = entry(&mut stream).unwrap();
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap().without_spans();
 
         assert_eq!(expected, actual);
     }
@@ -277,22 +404,22 @@ mod tests {
         let tokens = tokenise(&test).unwrap();
 
         let expected = 
-            Box::new(QueryTree::And {
-                q1 : Box::new(QueryTree::Op {
-                    left : "foo".to_owned(),
+            Box::new(QueryTree::And { span : NO_SPAN,
+                q1 : Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : col("foo"),
                     op : Op::Eq,
-                    right : "true".to_owned(),
+                    right : Operand::Literal("true".to_owned()),
                 }),
-                q2 : Box::new(QueryTree::Or {
-                    q1 : Box::new(QueryTree::Op {
-                        left : "bar".to_owned(),
+                q2 : Box::new(QueryTree::Or { span : NO_SPAN,
+                    q1 : Box::new(QueryTree::Op { span : NO_SPAN,
+                        left : col("bar"),
                         op : Op::Eq,
-                        right : "false".to_owned(),
+                        right : Operand::Literal("false".to_owned()),
                     }),
-                    q2 : Box::new(QueryTree::Op {
-                        left : "baz".to_owned(),
+                    q2 : Box::new(QueryTree::Op { span : NO_SPAN,
+                        left : col("baz"),
                         op : Op::Eq,
-                        right : "true".to_owned(),
+                        right : Operand::Literal("true".to_owned()),
                     }),
                 }),
             });
@@ -301,7 +428,37 @@ mod tests {
         
         let mut stream = tokens.iter().peekable();
         
-        let actual = entry(&mut stream).unwrap();
+        let actual = entry(&mut stream, &schema(), Precedence::Legacy).unwrap().without_spans();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or_and_both_are_left_associative()
+    {
+        let test = "foo = true || bar = false && baz = true && stock = x || name = y".to_owned();
+        let tokens = tokenise(&test).unwrap();
+
+        let op = |c:&str, v:&str|{
+            Box::new(QueryTree::Op { span : NO_SPAN, left : col(c), op : Op::Eq, right : Operand::Literal(v.to_owned()) })
+        };
+        let expected =
+            Box::new(QueryTree::Or { span : NO_SPAN,
+                q1 : Box::new(QueryTree::Or { span : NO_SPAN,
+                    q1 : op("foo", "true"),
+                    q2 : Box::new(QueryTree::And { span : NO_SPAN,
+                        q1 : Box::new(QueryTree::And { span : NO_SPAN, q1 : op("bar", "false"), q2 : op("baz", "true") }),
+                        q2 : op("stock", "x")
+                    })
+                }),
+                q2 : op("name", "y")
+            });
+
+        use query::query_tree::parsing::entry;
+
+        let mut stream = tokens.iter().peekable();
+
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap().without_spans();
 
         assert_eq!(expected, actual);
     }
@@ -313,23 +470,23 @@ mod tests {
         let tokens = tokenise(&test).unwrap();
 
         let expected = 
-            Box::new(QueryTree::Or {
-                q1 : Box::new(QueryTree::And {
-                    q1 : Box::new(QueryTree::Op {
-                        left : "foo".to_owned(),
+            Box::new(QueryTree::Or { span : NO_SPAN,
+                q1 : Box::new(QueryTree::And { span : NO_SPAN,
+                    q1 : Box::new(QueryTree::Op { span : NO_SPAN,
+                        left : col("foo"),
                         op : Op::Eq,
-                        right : "true".to_owned(),
+                        right : Operand::Literal("true".to_owned()),
                     }),
-                    q2 : Box::new(QueryTree::Op {
-                        left : "bar".to_owned(),
+                    q2 : Box::new(QueryTree::Op { span : NO_SPAN,
+                        left : col("bar"),
                         op : Op::Eq,
-                        right : "false".to_owned(),
+                        right : Operand::Literal("false".to_owned()),
                     }),
                 }),
-                q2 : Box::new(QueryTree::Op {
-                    left : "baz".to_owned(),
+                q2 : Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : col("baz"),
                     op : Op::Eq,
-                    right : "true".to_owned(),
+                    right : Operand::Literal("true".to_owned()),
                 }),
             });
 
@@ -337,7 +494,7 @@ mod tests {
         
         let mut stream = tokens.iter().peekable();
         
-        let actual = entry(&mut stream).unwrap();
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap().without_spans();
 
         assert_eq!(expected, actual);
     }
@@ -349,17 +506,17 @@ mod tests {
         let tokens = tokenise(&test).unwrap();
 
         let expected = 
-            Box::new(QueryTree::Op {
-                    left : "foo".to_owned(),
+            Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : col("foo"),
                     op : Op::Eq,
-                    right : "true".to_owned(),
+                    right : Operand::Literal("true".to_owned()),
                 });
 
         use query::query_tree::parsing::entry;
         
         let mut stream = tokens.iter().peekable();
         
-        let actual = entry(&mut stream).unwrap();
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap().without_spans();
 
         assert_eq!(expected, actual);
     }
@@ -374,7 +531,7 @@ mod tests {
         
         let mut stream = tokens.iter().peekable();
         
-        let actual = entry(&mut stream);
+        let actual = entry(&mut stream, &schema(), Precedence::Standard);
 
         match actual {
             Err (e) =>
@@ -386,4 +543,202 @@ mod tests {
         }
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn literals_can_be_either_side_of_an_op()
+    {
+        let test = "'VOD.L' = stock || name != \"O'Neil\"".to_owned();
+        let tokens = tokenise(&test).unwrap();
+
+        let expected = 
+            Box::new(QueryTree::Or { span : NO_SPAN,
+                q1 : Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : Operand::Literal("VOD.L".to_owned()),
+                    op : Op::Eq,
+                    right : col("stock"),
+                }),
+                q2 : Box::new(QueryTree::Op { span : NO_SPAN,
+                    left : col("name"),
+                    op : Op::NotEq,
+                    right : Operand::Literal("O'Neil".to_owned()),
+                }),
+            });
+
+        use query::query_tree::parsing::entry;
+        
+        let mut stream = tokens.iter().peekable();
+        
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap().without_spans();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn explicit_columns_must_exist_and_bare_words_fall_back_to_constants()
+    {
+        use query::query_tree::parsing::entry;
+
+        let tokens = tokenise("`stock` = name && $foo = bar && stock = volume").unwrap();
+        let mut stream = tokens.iter().peekable();
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap().without_spans();
+
+        let expected =
+            Box::new(QueryTree::And { span : NO_SPAN,
+                q1 : Box::new(QueryTree::And { span : NO_SPAN,
+                    q1 : Box::new(QueryTree::Op { span : NO_SPAN, left : col("stock"), op : Op::Eq, right : col("name") }),
+                    q2 : Box::new(QueryTree::Op { span : NO_SPAN, left : col("foo"), op : Op::Eq, right : col("bar") })
+                }),
+                q2 : Box::new(QueryTree::Op { span : NO_SPAN, left : col("stock"), op : Op::Eq, right : Operand::Literal("volume".to_owned()) })
+            });
+        assert_eq!(expected, actual);
+
+        let tokens = tokenise("$volume = 1").unwrap();
+        let mut stream = tokens.iter().peekable();
+        match entry(&mut stream, &schema(), Precedence::Standard) {
+            Err(e) => assert_eq!("Could not find column 'volume'", format!("{}", e)),
+            Ok(_) => panic!("was supposed to fail!")
+        }
+    }
+
+    #[test]
+    fn not_applies_to_the_next_expression()
+    {
+        let test = "!foo = true && !!(bar = false)".to_owned();
+        let tokens = tokenise(&test).unwrap();
+
+        let expected =
+            Box::new(QueryTree::And { span : NO_SPAN,
+                q1 : Box::new(QueryTree::Not { span : NO_SPAN,
+                    q : Box::new(QueryTree::Op { span : NO_SPAN, left : col("foo"), op : Op::Eq, right : Operand::Literal("true".to_owned()) })
+                }),
+                q2 : Box::new(QueryTree::Not { span : NO_SPAN,
+                    q : Box::new(QueryTree::Not { span : NO_SPAN,
+                        q : Box::new(QueryTree::Op { span : NO_SPAN, left : col("bar"), op : Op::Eq, right : Operand::Literal("false".to_owned()) })
+                    })
+                })
+            });
+
+        use query::query_tree::parsing::entry;
+
+        let mut stream = tokens.iter().peekable();
+
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap().without_spans();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn unexpected_and_trailing_tokens_are_errors()
+    {
+        use query::query_tree::parsing::entry;
+
+        let cases = [
+            ("&& foo = true", "Expected a comparison, '!' or '(', found '&&'"),
+            (") x", "Expected a comparison, '!' or '(', found ')'"),
+            ("foo = true)", "Unexpected ')' after the end of the query"),
+            ("foo = true bar = false", "Unexpected 'bar' after the end of the query"),
+            ("foo = ", "Expected a column or value, found <EOL>"),
+            ("foo true", "Expected op, found 'true'"),
+            ("", "Expected a comparison, '!' or '(', found <EOL>")
+        ];
+
+        for &(q, expected) in cases.iter() {
+            let tokens = tokenise(q).unwrap();
+            let mut stream = tokens.iter().peekable();
+            match entry(&mut stream, &schema(), Precedence::Standard) {
+                Err(e) => assert_eq!(expected, format!("{}", e), "for '{}'", q),
+                Ok(_) => panic!("'{}' was supposed to fail!", q)
+            }
+        }
+    }
+
+    #[test]
+    fn is_null_needs_a_column()
+    {
+        use query::query_tree::parsing::entry;
+
+        let tokens = tokenise("stock is null && `name` IS NOT NULL").unwrap();
+        let mut stream = tokens.iter().peekable();
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap();
+        let column = |name:&str|{ ColumnRef { name : name.to_owned(), index : schema().try_find_col(name).unwrap().0 } };
+        let expected =
+            QueryTree::And { span : NO_SPAN,
+                q1 : Box::new(QueryTree::IsNull { column : column("stock"), negated : false, span : NO_SPAN }),
+                q2 : Box::new(QueryTree::IsNull { column : column("name"), negated : true, span : NO_SPAN })
+            };
+        assert_eq!(Span { start : 0, end : 35 }, actual.span());
+        assert_eq!(Box::new(expected), actual.without_spans());
+
+        for &(q, expected) in [("stok IS NULL", "Could not find column 'stok', did you mean 'stock'?"), ("stock IS 1", "Expected NULL, found '1'")].iter() {
+            let tokens = tokenise(q).unwrap();
+            let mut stream = tokens.iter().peekable();
+            assert_eq!(expected, entry(&mut stream, &schema(), Precedence::Standard).err().unwrap().to_string(), "for '{}'", q);
+        }
+    }
+
+    #[test]
+    fn nodes_know_where_they_came_from()
+    {
+        use query::query_tree::parsing::entry;
+
+        let tokens = tokenise("!foo = true || (bar = false)").unwrap();
+        let mut stream = tokens.iter().peekable();
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap();
+
+        assert_eq!(Span { start : 0, end : 27 }, actual.span());
+        match *actual {
+            QueryTree::Or { ref q1, ref q2, .. } => {
+                assert_eq!(Span { start : 0, end : 11 }, q1.span());
+                assert_eq!(Span { start : 16, end : 27 }, q2.span());
+            }
+            _ => panic!("expected an or")
+        }
+    }
+
+    #[test]
+    fn a_minus_before_a_number_makes_it_negative()
+    {
+        use query::query_tree::parsing::entry;
+
+        let tokens = tokenise("foo > -5 && -1.5 <= bar").unwrap();
+        let mut stream = tokens.iter().peekable();
+        let actual = entry(&mut stream, &schema(), Precedence::Standard).unwrap();
+        let expected =
+            QueryTree::And { span : NO_SPAN,
+                q1 : Box::new(QueryTree::Op { span : NO_SPAN, left : col("foo"), op : Op::Gt, right : Operand::Literal("-5".to_owned()) }),
+                q2 : Box::new(QueryTree::Op { span : NO_SPAN, left : Operand::Literal("-1.5".to_owned()), op : Op::LEq, right : col("bar") })
+            };
+        assert_eq!(Box::new(expected), actual.without_spans());
+
+        for q in ["foo > - 5", "foo > -bar"].iter() {
+            let tokens = tokenise(q).unwrap();
+            let mut stream = tokens.iter().peekable();
+            assert_eq!("Expected a column or value, found '-'", entry(&mut stream, &schema(), Precedence::Standard).err().unwrap().to_string(), "for '{}'", q);
+        }
+    }
+
+    #[test]
+    fn word_operators_are_only_keywords_where_an_operator_can_be()
+    {
+        use query::query_tree::parsing::entry;
+
+        let schema = Schema::of(&["foo[bool]", "not[int]", "and[int]", "or[int?]"]);
+        let parse = |q:&str|{
+            let tokens = tokenise(q).unwrap();
+            let mut stream = tokens.iter().peekable();
+            entry(&mut stream, &schema, Precedence::Standard).map(|t|{ t.without_spans() }).map_err(|e|{ e.to_string() })
+        };
+
+        let cases = [
+            ("NOT foo = true and foo = false Or foo = true", "!foo = true && foo = false || foo = true"),
+            ("not = 1", "`not` = 1"),
+            ("or = 1 OR and > 2 AND not < 3", "$or = 1 || $and > 2 && $not < 3"),
+            ("NOT not = 1", "!$not = 1"),
+            ("not or IS NULL && or IS NOT NULL", "!$or IS NULL && $or IS NOT NULL"),
+            ("not not IS not null", "!$not IS NOT NULL")
+        ];
+        for &(q, same_as) in cases.iter() {
+            assert_eq!(parse(same_as).unwrap(), parse(q).unwrap(), "for '{}'", q);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use query::parse_error::{ErrorKind, ParseError};
+use query::tokens::{negative_number, peek, Token, TokenStream};
+use query::query_tree::{ArithOp, ColumnRef, Expr, OrderKey, Precedence, SelectItem, Statement};
+use query::query_tree::parsing;
+use query::query_tree::parsing::{is_keyword, keyword};
+use schema::Schema;
+
+// statement := SELECT items [WHERE S] [ORDER BY keys] [LIMIT number]
+// items := item | item , items
+// item := * | arith | arith AS ident
+// arith := term | arith + term | arith - term
+// term := factor | term * factor | term / factor
+// factor := ident | literal | column | -number | (arith)
+// keys := key | key , keys
+// key := name | name ASC | name DESC, where a name is an ident or a column
+// keywords are case insensitive, S is the filter grammar from parsing.rs
+
+const KEYWORDS : [&str; 8] = ["SELECT", "WHERE", "ORDER", "BY", "LIMIT", "AS", "ASC", "DESC"];
+
+fn name(p : &mut TokenStream) -> Result<String, ParseError>
+{
+    match peek(p) {
+        Some(Token::ConstOrIdentifier(id))
+            if !KEYWORDS.iter().any(|kw|{ id.eq_ignore_ascii_case(kw) }) =>
+            {
+                p.next().unwrap();
+                Ok(id.clone())
+            }
+        Some(Token::Column(id)) =>
+            {
+                p.next().unwrap();
+                Ok(id.clone())
+            }
+        _ => Err(ParseError::expected("a name", p.peek()))
+    }
+}
+
+pub fn entry(p : &mut TokenStream, s : &Schema, prec : Precedence) -> Result<Statement, ParseError>
+{
+    keyword("SELECT", p)?;
+    let select = items(p, s)?;
+
+    let filter =
+        if is_keyword(peek(p), "WHERE") {
+            p.next().unwrap();
+            Some(parsing::condition(p, s, prec)?)
+        }
+        else {
+            None
+        };
+
+    let order_by =
+        if is_keyword(peek(p), "ORDER") {
+            p.next().unwrap();
+            keyword("BY", p)?;
+            keys(p)?
+        }
+        else {
+            Vec::new()
+        };
+
+    let limit =
+        if is_keyword(peek(p), "LIMIT") {
+            p.next().unwrap();
+            let count = p.next();
+            let n =
+                match count.map(|t|{ &t.token }) {
+                    Some(Token::ConstOrIdentifier(n)) => n.parse::<usize>().ok(),
+                    _ => None
+                };
+            Some(n.ok_or_else(||{ ParseError::expected("a row count after LIMIT", count.as_ref()) })?)
+        }
+        else {
+            None
+        };
+
+    match p.peek() {
+        None => Ok(Statement { select, filter, order_by, limit }),
+        Some(tok) => Err(ParseError::new(ErrorKind::Trailing(tok.token.clone()), Some(tok.span)))
+    }
+}
+
+fn items(p : &mut TokenStream, s : &Schema) -> Result<Vec<SelectItem>, ParseError>
+{
+    let mut items = vec!(item(p, s)?);
+    while let Some(&Token::Comma) = peek(p) {
+        p.next().unwrap();
+        items.push(item(p, s)?);
+    }
+    Ok(items)
+}
+
+fn item(p : &mut TokenStream, s : &Schema) -> Result<SelectItem, ParseError>
+{
+    if let Some(&Token::Star) = peek(p) {
+        p.next().unwrap();
+        return Ok(SelectItem::All);
+    }
+
+    let expr = arith(p, s)?;
+    let alias =
+        if is_keyword(peek(p), "AS") {
+            p.next().unwrap();
+            Some(name(p)?)
+        }
+        else {
+            None
+        };
+    Ok(SelectItem::Expr { expr, alias })
+}
+
+pub fn arith(p : &mut TokenStream, s : &Schema) -> Result<Expr, ParseError>
+{
+    let mut left = term(p, s)?;
+    loop {
+        let op =
+            match peek(p) {
+                Some(&Token::Plus) => ArithOp::Add,
+                Some(&Token::Minus) => ArithOp::Sub,
+                _ => return Ok(left)
+            };
+        p.next().unwrap();
+        let right = term(p, s)?;
+        left = Expr::Arith { left : Box::new(left), op, right : Box::new(right) };
+    }
+}
+
+fn term(p : &mut TokenStream, s : &Schema) -> Result<Expr, ParseError>
+{
+    let mut left = factor(p, s)?;
+    loop {
+        let op =
+            match peek(p) {
+                Some(&Token::Star) => ArithOp::Mul,
+                Some(&Token::Slash) => ArithOp::Div,
+                _ => return Ok(left)
+            };
+        p.next().unwrap();
+        let right = factor(p, s)?;
+        left = Expr::Arith { left : Box::new(left), op, right : Box::new(right) };
+    }
+}
+
+fn factor(p : &mut TokenStream, s : &Schema) -> Result<Expr, ParseError>
+{
+    if let Some((number, _)) = negative_number(p) {
+        return Ok(Expr::Const(number));
+    }
+    match peek(p) {
+        Some(&Token::OpenBracket) => {
+            p.next().unwrap();
+            let inner = arith(p, s)?;
+            match peek(p) {
+                Some(&Token::CloseBracket) => {
+                    p.next().unwrap();
+                    Ok(inner)
+                }
+                _ => Err(ParseError::expected("')'", p.peek()))
+            }
+        }
+        Some(Token::Literal(lit)) => {
+            p.next().unwrap();
+            Ok(Expr::Literal(lit.clone()))
+        }
+        Some(Token::Column(name)) => {
+            let span = p.next().unwrap().span;
+            Ok(Expr::Column(ColumnRef::resolve(name, span, s)?))
+        }
+        _ => {
+            let word = name(p)?;
+            match ColumnRef::find(&word, s) {
+                Some(c) => Ok(Expr::Column(c)),
+                None => Ok(Expr::Const(word))
+            }
+        }
+    }
+}
+
+fn keys(p : &mut TokenStream) -> Result<Vec<OrderKey>, ParseError>
+{
+    let mut keys = Vec::new();
+    loop {
+        let name = name(p)?;
+        let descending =
+            if is_keyword(peek(p), "DESC") {
+                p.next().unwrap();
+                true
+            }
+            else {
+                if is_keyword(peek(p), "ASC") {
+                    p.next().unwrap();
+                }
+                false
+            };
+        keys.push(OrderKey { name, descending });
+
+        match peek(p) {
+            Some(&Token::Comma) => { p.next().unwrap(); }
+            _ => return Ok(keys)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use query::query_tree::{ArithOp, ColumnRef, Expr, OrderKey, Precedence, QueryTree, SelectItem, Statement, Op, Operand};
+    use query::tokens::Span;
+    use schema::Schema;
+
+    const NO_SPAN : Span = Span { start : 0, end : 0 };
+
+    fn column(name:&str) -> ColumnRef {
+        let index = Schema::trades().try_find_col(name).unwrap().0;
+        ColumnRef { name : name.to_owned(), index }
+    }
+
+    fn ident(s:&str) -> Expr {
+        match Schema::trades().try_find_col(s) {
+            Some(_) => Expr::Column(column(s)),
+            None => Expr::Const(s.to_owned())
+        }
+    }
+
+    #[test]
+    fn full_statement_parses()
+    {
+        let test = "SELECT stock, price*size AS notional WHERE executed = true ORDER BY notional DESC LIMIT 10";
+
+        let expected = Statement {
+            select : vec!(
+                SelectItem::Expr { expr : ident("stock"), alias : None },
+                SelectItem::Expr {
+                    expr : Expr::Arith { left : Box::new(ident("price")), op : ArithOp::Mul, right : Box::new(ident("size")) },
+                    alias : Some("notional".to_owned())
+                }
+            ),
+            filter : Some(Box::new(QueryTree::Op { span : NO_SPAN,
+                left : Operand::Column(column("executed")),
+                op : Op::Eq,
+                right : Operand::Literal("true".to_owned())
+            })),
+            order_by : vec!(OrderKey { name : "notional".to_owned(), descending : true }),
+            limit : Some(10)
+        };
+
+        let mut actual = Statement::from_qstring(test, &Schema::trades(), Precedence::Standard).unwrap();
+        actual.filter = actual.filter.map(|f|{ f.without_spans() });
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive_and_clauses_optional()
+    {
+        let actual = Statement::from_qstring("select * order by a, b asc", &Schema::trades(), Precedence::Standard).unwrap();
+
+        let expected = Statement {
+            select : vec!(SelectItem::All),
+            filter : None,
+            order_by : vec!(
+                OrderKey { name : "a".to_owned(), descending : false },
+                OrderKey { name : "b".to_owned(), descending : false }
+            ),
+            limit : None
+        };
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn arithmetic_has_usual_precedence()
+    {
+        let actual = Statement::from_qstring("SELECT a + b * c - (d - e) AS x", &Schema::trades(), Precedence::Standard).unwrap();
+
+        let expected =
+            Expr::Arith {
+                left : Box::new(Expr::Arith {
+                    left : Box::new(ident("a")),
+                    op : ArithOp::Add,
+                    right : Box::new(Expr::Arith { left : Box::new(ident("b")), op : ArithOp::Mul, right : Box::new(ident("c")) })
+                }),
+                op : ArithOp::Sub,
+                right : Box::new(Expr::Arith { left : Box::new(ident("d")), op : ArithOp::Sub, right : Box::new(ident("e")) })
+            };
+
+        assert_eq!(vec!(SelectItem::Expr { expr : expected, alias : Some("x".to_owned()) }), actual.select);
+    }
+
+    #[test]
+    fn a_minus_before_a_number_is_only_a_sign_where_an_operand_is_expected()
+    {
+        let actual = Statement::from_qstring("SELECT a - -5 AS x, a -5 AS y, -2 * a AS z", &Schema::trades(), Precedence::Standard).unwrap();
+
+        let minus_5 = Expr::Arith { left : Box::new(ident("a")), op : ArithOp::Sub, right : Box::new(Expr::Const("-5".to_owned())) };
+        let sub_5 = Expr::Arith { left : Box::new(ident("a")), op : ArithOp::Sub, right : Box::new(ident("5")) };
+        let times = Expr::Arith { left : Box::new(Expr::Const("-2".to_owned())), op : ArithOp::Mul, right : Box::new(ident("a")) };
+        let expected = vec!(
+            SelectItem::Expr { expr : minus_5, alias : Some("x".to_owned()) },
+            SelectItem::Expr { expr : sub_5, alias : Some("y".to_owned()) },
+            SelectItem::Expr { expr : times, alias : Some("z".to_owned()) }
+        );
+        assert_eq!(expected, actual.select);
+    }
+
+    #[test]
+    fn malformed_statements_are_errors()
+    {
+        for q in ["SELECT", "SELECT a,", "SELECT WHERE a = 1", "SELECT a LIMIT ten", "SELECT a ORDER a", "SELECT a b", "SELECT $volume"].iter() {
+            assert!(Statement::from_qstring(q, &Schema::trades(), Precedence::Standard).is_err(), "'{}' should not parse", q);
+        }
+    }
+
+    #[test]
+    fn select_is_detected()
+    {
+        assert!(Statement::is_statement("  select a"));
+        assert!(Statement::is_statement("SELECT is, stock"));
+        assert!(Statement::is_statement("SELECT 'unterminated"));
+        assert!(!Statement::is_statement("a = 1"));
+        // a filter on a column called select
+        for q in ["select = 1", "SELECT != 'x' && a = 1", "select >= 2", "select IS NULL", "select is not null"] {
+            assert!(!Statement::is_statement(q), "for '{}'", q);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::cell::OnceCell;
+use std::sync::Arc;
+use std::str;
+
+use csv::ByteRecord;
+
+use error::{CsvFiltError, Result};
+use schema::{ColType, Schema};
+use value::Value;
+
+// A record being run through a query. Each column's typed value is read the first time
+// something asks for it and kept for the rest of the row, however many predicates,
+// computed columns and sort keys use it. Columns nothing asks for are never decoded.
+pub struct Row<'a> {
+    record : &'a ByteRecord,
+    values : &'a [OnceCell<Value>]
+}
+
+// The storage behind a Row, kept between rows so a run only allocates it once
+#[derive(Default)]
+pub struct RowValues(Vec<OnceCell<Value>>);
+
+impl RowValues {
+    pub fn row<'a>(&'a mut self, record:&'a ByteRecord) -> Row<'a> {
+        for v in self.0.iter_mut() {
+            v.take();
+        }
+        self.0.resize_with(record.len(), OnceCell::new);
+        Row { record, values : &self.0 }
+    }
+}
+
+// A column of the input, read as its type
+pub struct Cell {
+    pub index : usize,
+    pub name : String,
+    pub col_type : Arc<ColType>,
+    // the values read as null, if the column is nullable
+    nulls : Option<Arc<[String]>>,
+    // the number of columns in the schema, which records should have
+    width : usize
+}
+
+impl Cell {
+    pub fn new(index:usize, s:&Schema) -> Cell {
+        let col = s.col(index);
+        Cell { index, name : col.name().to_owned(), col_type : col.col_type.clone(), nulls : col.nulls.clone(), width : s.len() }
+    }
+
+    pub fn nullable(&self) -> bool {
+        self.nulls.is_some()
+    }
+
+    // The column's type as written in a header
+    pub fn spec(&self) -> String {
+        self.col_type.spec(self.nullable())
+    }
+
+    fn is_null(&self, s:&str) -> bool {
+        self.nulls.as_ref().is_some_and(|n|{ n.iter().any(|n|{ n == s }) })
+    }
+
+    // The cell's value, or an error if the record is too short to have the column. The CLI
+    // checks field counts first, but records given to the library API may not be checked.
+    pub fn value<'r>(&self, row:&Row<'r>) -> Result<&'r Value> {
+        let bytes = row.record.get(self.index)
+            .ok_or(CsvFiltError::FieldCount { expected : self.width, found : row.record.len(), record : None })?;
+        let slot = &row.values[self.index];
+        if let Some(v) = slot.get() {
+            return Ok(v);
+        }
+        let v =
+            match str::from_utf8(bytes) {
+                Ok(s) if self.is_null(s.trim()) => Ok(Value::Null),
+                Ok(s) => (self.col_type.value)(s.trim()),
+                Err(_) => Err(CsvFiltError::CellParse {
+                    column : None,
+                    record : None,
+                    value : String::from_utf8_lossy(bytes).into_owned(),
+                    type_name : self.col_type.name.clone()
+                })
+            };
+        let v = v.map_err(|e|{ e.in_column(&self.name) })?;
+        Ok(slot.get_or_init(||{ v }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{ByteRecord, StringRecord};
+
+    use query::row::{Cell, RowValues};
+    use schema::Schema;
+    use value::Value;
+
+    #[test]
+    fn values_are_read_when_first_used_and_kept_for_the_row() {
+        let schema = Schema::from_header(&StringRecord::from(vec!("stock[string]", "size[int]"))).unwrap();
+        let size = Cell::new(1, &schema);
+        let mut values = RowValues::default();
+
+        for (record, expected) in [(vec!("VOD.L", "100"), 100), (vec!("AAPL.O", " 200 "), 200)] {
+            let record = StringRecord::from(record).into_byte_record();
+            let row = values.row(&record);
+            assert!(row.values.iter().all(|v|{ v.get().is_none() }));
+            assert_eq!(&Value::Int(expected), size.value(&row).unwrap());
+            assert_eq!(Some(&Value::Int(expected)), row.values[1].get());
+            assert_eq!(None, row.values[0].get());
+        }
+    }
+
+    #[test]
+    fn bad_values_name_their_column() {
+        let schema = Schema::from_header(&StringRecord::from(vec!("stock[string]", "size[int]"))).unwrap();
+        let mut values = RowValues::default();
+        let size = Cell::new(1, &schema);
+
+        let record = StringRecord::from(vec!("VOD.L", "lots")).into_byte_record();
+        let e = size.value(&values.row(&record)).err().unwrap();
+        assert_eq!("Could not make a int from 'lots' in column size", e.to_string());
+
+        let record = ByteRecord::from(vec!(&b"VOD.L"[..], &b"1\xff"[..]));
+        let e = size.value(&values.row(&record)).err().unwrap();
+        assert_eq!("Could not make a int from '1\u{fffd}' in column size", e.to_string());
+    }
+
+    #[test]
+    fn columns_nothing_reads_are_not_decoded() {
+        let schema = Schema::from_header(&StringRecord::from(vec!("stock[string]", "size[int]"))).unwrap();
+        let record = ByteRecord::from(vec!(&b"\xffVOD.L"[..], &b"100"[..]));
+        let mut values = RowValues::default();
+        assert_eq!(&Value::Int(100), Cell::new(1, &schema).value(&values.row(&record)).unwrap());
+    }
+
+    #[test]
+    fn only_nullable_columns_read_null_values_as_null() {
+        let header = StringRecord::from(vec!("size[int?]", "count[int]"));
+        let schema = Schema::from_header_with_null_values(&header, &["", "NA"]).unwrap();
+        let (size, count) = (Cell::new(0, &schema), Cell::new(1, &schema));
+        let mut values = RowValues::default();
+
+        for cell in ["", " NA ", "NA"] {
+            let record = StringRecord::from(vec!(cell, "1")).into_byte_record();
+            assert_eq!(&Value::Null, size.value(&values.row(&record)).unwrap());
+        }
+        let record = StringRecord::from(vec!("N/A", "NA")).into_byte_record();
+        assert!(size.value(&values.row(&record)).is_err());
+        assert_eq!("Could not make a int from 'NA' in column count", count.value(&values.row(&record)).err().unwrap().to_string());
+        assert_eq!(("int?".to_owned(), "int".to_owned()), (size.spec(), count.spec()));
+    }
+}
@@ -0,0 +1,765 @@
+use std::cmp::Ordering;
+use std::io::Write;
+use std::sync::Arc;
+
+use csv::{ByteRecord, Writer};
+use rust_decimal::Decimal;
+
+use error::{CsvFiltError, Result};
+use schema::Schema;
+use value::Value;
+
+use query::{ParseError, QueryFn};
+use query::row::{Cell, Row, RowValues};
+use query::parse_error::ErrorKind;
+use query::query_tree::{ArithOp, Expr, SelectItem, Statement};
+
+fn unknown_column(name:String, s:&Schema) -> CsvFiltError {
+    let suggestion = s.suggest(&name).map(|c|{ c.to_owned() });
+    CsvFiltError::Parse(ParseError::new(ErrorKind::UnknownColumn { name, suggestion }, None))
+}
+
+// A value computed from each row
+enum Computed {
+    Column(Cell),
+    Const(Value),
+    // with the type of the result
+    Arith(Box<Computed>, ArithOp, Box<Computed>, NumType)
+}
+
+// An integer type by sign and width, e.g. u32, which arithmetic on integers is checked against
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct IntType {
+    signed : bool,
+    bits : u32
+}
+
+impl IntType {
+    fn from_name(name:&str) -> Option<IntType> {
+        if name == "int" {
+            return Some(IntType { signed : true, bits : 64 });
+        }
+        let signed =
+            match name.chars().next() {
+                Some('i') => true,
+                Some('u') => false,
+                _ => return None
+            };
+        match name[1..].parse() {
+            Ok(bits @ 8) | Ok(bits @ 16) | Ok(bits @ 32) | Ok(bits @ 64) | Ok(bits @ 128) => Some(IntType { signed, bits }),
+            _ => None
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            IntType { signed : true, bits : 64 } => "int".to_owned(),
+            IntType { signed, bits } => format!("{}{}", if signed { 'i' } else { 'u' }, bits)
+        }
+    }
+
+    // The type of a result from the two, wide enough for any value of either. Mixing signs
+    // needs a signed type twice the unsigned one's width, as far as 128 bits goes.
+    fn combine(a:IntType, b:IntType) -> IntType {
+        if a.signed == b.signed {
+            return IntType { signed : a.signed, bits : a.bits.max(b.bits) };
+        }
+        let (s, u) = if a.signed { (a, b) } else { (b, a) };
+        IntType { signed : true, bits : s.bits.max(u.bits * 2).min(128) }
+    }
+
+    fn holds_signed(self, v:i128) -> bool {
+        self.bits == 128 || (v >= -(1 << (self.bits - 1)) && v < 1 << (self.bits - 1))
+    }
+
+    fn holds_unsigned(self, v:u128) -> bool {
+        self.bits == 128 || v < 1 << self.bits
+    }
+}
+
+// The type of an arithmetic result, which decides how it is worked out
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum NumType {
+    Int(IntType),
+    Decimal,
+    Float
+}
+
+impl NumType {
+    fn from_name(name:&str) -> Option<NumType> {
+        match name {
+            "float" => Some(NumType::Float),
+            "decimal" => Some(NumType::Decimal),
+            _ => IntType::from_name(name).map(NumType::Int)
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            NumType::Int(t) => t.name(),
+            NumType::Decimal => "decimal".to_owned(),
+            NumType::Float => "float".to_owned()
+        }
+    }
+
+    // A float on either side makes the result inexact anyway; otherwise a decimal keeps it exact
+    fn combine(a:NumType, b:NumType) -> NumType {
+        match (a, b) {
+            (NumType::Int(a), NumType::Int(b)) => NumType::Int(IntType::combine(a, b)),
+            (NumType::Float, _) | (_, NumType::Float) => NumType::Float,
+            _ => NumType::Decimal
+        }
+    }
+}
+
+impl Computed {
+    fn compile(e:Expr, s:&Schema) -> Result<Computed> {
+        match e {
+            Expr::Column(c) => Ok(Computed::Column(Cell::new(c.index, s))),
+            Expr::Const(name) => {
+                if let Ok(i) = name.parse::<i64>() {
+                    Ok(Computed::Const(Value::Int(i.into())))
+                }
+                // exact, so decimal columns stay exact; float columns make the result a float anyway
+                else if let Ok(d) = name.parse::<Decimal>() {
+                    Ok(Computed::Const(Value::Decimal(d)))
+                }
+                else if let Ok(f) = name.parse::<f64>() {
+                    Ok(Computed::Const(Value::Float(f)))
+                }
+                else {
+                    Err(unknown_column(name, s))
+                }
+            }
+            Expr::Literal(s) => Ok(Computed::Const(Value::Str(s))),
+            Expr::Arith { left, op, right } => {
+                let (left, right) = (Computed::compile(*left, s)?, Computed::compile(*right, s)?);
+                let (l, r) = (left.type_name()?, right.type_name()?);
+                let num_type =
+                    match (NumType::from_name(&l), NumType::from_name(&r)) {
+                        (Some(a), Some(b)) => NumType::combine(a, b),
+                        _ => return Err(CsvFiltError::TypeMismatch { op : op.to_string(), left : l, right : r })
+                    };
+                Ok(Computed::Arith(Box::new(left), op, Box::new(right), num_type))
+            }
+        }
+    }
+
+    fn type_name(&self) -> Result<String> {
+        match *self {
+            Computed::Column(ref c) => Ok(c.col_type.name.clone()),
+            Computed::Const(Value::Int(_)) => Ok("int".to_owned()),
+            Computed::Const(Value::Float(_)) => Ok("float".to_owned()),
+            Computed::Const(Value::Decimal(_)) => Ok("decimal".to_owned()),
+            Computed::Const(Value::Str(_)) => Ok("string".to_owned()),
+            // constants are only ever numbers and quoted literals
+            Computed::Const(ref v) => unreachable!("constant {:?} in a computed column", v),
+            Computed::Arith(_, _, _, num_type) => Ok(num_type.name())
+        }
+    }
+
+    // Whether the value can be null, which it is if any column it uses is null
+    fn nullable(&self) -> bool {
+        match *self {
+            Computed::Column(ref c) => c.nullable(),
+            Computed::Const(_) => false,
+            Computed::Arith(ref l, _, ref r, _) => l.nullable() || r.nullable()
+        }
+    }
+
+    fn eval(&self, row:&Row) -> Result<Value> {
+        match *self {
+            Computed::Column(ref c) => c.value(row).cloned(),
+            Computed::Const(ref v) => Ok(v.clone()),
+            Computed::Arith(ref l, op, ref r, num_type) => {
+                let (a, b) = (l.eval(row)?, r.eval(row)?);
+                if a.is_null() || b.is_null() {
+                    return Ok(Value::Null);
+                }
+                arith(op, a, b, num_type)
+            }
+        }
+    }
+}
+
+// `op` on two integers of the same width, or two decimals, None if it overflows
+macro_rules! checked {
+    ($op:expr, $a:expr, $b:expr) => {
+        match $op {
+            ArithOp::Add => $a.checked_add($b),
+            ArithOp::Sub => $a.checked_sub($b),
+            ArithOp::Mul => $a.checked_mul($b),
+            ArithOp::Div => $a.checked_div($b)
+        }
+    }
+}
+
+fn arith(op:ArithOp, a:Value, b:Value, num_type:NumType) -> Result<Value> {
+    if num_type != NumType::Float && op == ArithOp::Div && b.as_f64() == Some(0.0) {
+        return Err(CsvFiltError::Arithmetic(format!("Division by zero in {} / {}", a, b)));
+    }
+    if num_type == NumType::Decimal {
+        let v = a.as_decimal().and_then(|x|{ b.as_decimal().and_then(|y|{ checked!(op, x, y) }) });
+        return v.map(Value::Decimal).ok_or_else(||{ CsvFiltError::Arithmetic(format!("Decimal overflow in {} {} {}", a, op, b)) });
+    }
+    if let NumType::Int(t) = num_type {
+        // worked out at full width, then checked against the type of the result
+        let v =
+            if t.signed {
+                a.as_i128().and_then(|x|{ b.as_i128().and_then(|y|{ checked!(op, x, y) }) })
+                    .filter(|&v|{ t.holds_signed(v) }).map(Value::Int)
+            }
+            else {
+                a.as_u128().and_then(|x|{ b.as_u128().and_then(|y|{ checked!(op, x, y) }) })
+                    .filter(|&v|{ t.holds_unsigned(v) }).map(Value::UInt)
+            };
+        return v.ok_or_else(||{ CsvFiltError::Arithmetic(format!("Integer overflow in {} {} {}", a, op, b)) });
+    }
+    match (a.as_f64(), b.as_f64()) {
+        (Some(x), Some(y)) =>
+            Ok(Value::Float(
+                match op {
+                    ArithOp::Add => x + y,
+                    ArithOp::Sub => x - y,
+                    ArithOp::Mul => x * y,
+                    ArithOp::Div => x / y
+                })),
+        _ => Err(CsvFiltError::TypeMismatch { op : op.to_string(), left : format!("{:?}", a), right : format!("{:?}", b) })
+    }
+}
+
+enum OutputColumn {
+    // written out exactly as read
+    Cell(Cell),
+    Computed(Computed)
+}
+
+impl OutputColumn {
+    fn value(&self, row:&Row) -> Result<Value> {
+        match *self {
+            OutputColumn::Cell(ref c) => c.value(row).cloned(),
+            OutputColumn::Computed(ref c) => c.eval(row)
+        }
+    }
+}
+
+enum SortKey {
+    Output(usize),
+    Column(Cell)
+}
+
+/// The rows a query produces, after filtering, projection, ordering and limiting.
+pub struct SelectQuery {
+    filter : Option<QueryFn>,
+    header : Vec<String>,
+    columns : Vec<OutputColumn>,
+    order_by : Vec<(SortKey, bool)>,
+    limit : Option<usize>,
+    // the number of fields every input record has
+    width : usize,
+    // every input column is output unchanged and in order, so matching records can be
+    // written out as they were read
+    passthrough : bool
+}
+
+// The result of running one record through a query
+pub struct OutputRow {
+    // None when the record is written out unchanged
+    fields : Option<ByteRecord>,
+    keys : Vec<Value>
+}
+
+impl SelectQuery {
+    fn new(filter:Option<QueryFn>, header:Vec<String>, columns:Vec<OutputColumn>, order_by:Vec<(SortKey, bool)>, limit:Option<usize>, s:&Schema) -> SelectQuery {
+        let passthrough =
+            order_by.is_empty() &&
+            columns.len() == s.len() &&
+            columns.iter().enumerate().all(|(i, c)|{
+                match *c {
+                    OutputColumn::Cell(ref c) => c.index == i,
+                    OutputColumn::Computed(_) => false
+                }
+            });
+        SelectQuery { filter, header, columns, order_by, limit, width : s.len(), passthrough }
+    }
+
+    /// every row that passes the filter, with the given columns written unchanged
+    pub fn from_filter(filter:QueryFn, columns:&[usize], s:&Schema) -> SelectQuery {
+        let header = columns.iter().map(|&idx|{ format!("{}[{}]", s.col(idx).name(), s.col(idx).spec()) }).collect();
+        let columns = columns.iter().map(|&idx|{ OutputColumn::Cell(Cell::new(idx, s)) }).collect();
+        SelectQuery::new(Some(filter), header, columns, Vec::new(), None, s)
+    }
+
+    // `filter` is the statement's WHERE clause, already compiled
+    pub fn from_statement(st:Statement, filter:Option<QueryFn>, s:&Schema) -> Result<SelectQuery> {
+        let mut names = Vec::new();
+        let mut header = Vec::new();
+        let mut columns = Vec::new();
+
+        for (n, item) in st.select.into_iter().enumerate() {
+            match item {
+                SelectItem::All => {
+                    for (idx, col) in s.iter().enumerate() {
+                        names.push(col.name().to_owned());
+                        header.push(format!("{}[{}]", col.name(), col.spec()));
+                        columns.push(OutputColumn::Cell(Cell::new(idx, s)));
+                    }
+                }
+                SelectItem::Expr { expr, alias } => {
+                    let c = Computed::compile(expr, s)?;
+                    // columns are written as read, so keep any format their header gave; computed
+                    // nulls are written as empty cells
+                    let type_name =
+                        match c {
+                            Computed::Column(ref c) => c.spec(),
+                            _ if c.nullable() => format!("{}?", c.type_name()?),
+                            _ => c.type_name()?
+                        };
+                    let (name, col) =
+                        match (c, alias) {
+                            (Computed::Column(c), alias) => {
+                                let name = alias.unwrap_or_else(||{ c.name.clone() });
+                                (name, OutputColumn::Cell(c))
+                            }
+                            (c, Some(alias)) => (alias, OutputColumn::Computed(c)),
+                            (_, None) =>
+                                return Err(CsvFiltError::Parse(ParseError::new(ErrorKind::MissingAlias(n + 1), None)))
+                        };
+                    header.push(format!("{}[{}]", name, type_name));
+                    names.push(name);
+                    columns.push(col);
+                }
+            }
+        }
+
+        let mut order_by = Vec::new();
+        for key in st.order_by {
+            let sort_key =
+                if let Some(pos) = names.iter().position(|n|{ *n == key.name }) {
+                    SortKey::Output(pos)
+                }
+                else if let Some((idx, _)) = s.try_find_col(&key.name) {
+                    SortKey::Column(Cell::new(idx, s))
+                }
+                else {
+                    return Err(unknown_column(key.name, s));
+                };
+            order_by.push((sort_key, key.descending));
+        }
+
+        Ok(SelectQuery::new(filter, header, columns, order_by, st.limit, s))
+    }
+
+    /// The typed header of the output
+    pub fn header(&self) -> &[String] {
+        &self.header
+    }
+
+    // The output for `record`, or None if it doesn't match
+    fn run_row(&self, record:&ByteRecord, tag:Option<&str>, values:&mut RowValues) -> Result<Option<OutputRow>> {
+        if record.len() != self.width {
+            return Err(CsvFiltError::FieldCount { expected : self.width, found : record.len(), record : None });
+        }
+
+        let row = values.row(record);
+
+        if let Some(ref f) = self.filter {
+            if !f.matches_row(&row)? {
+                return Ok(None);
+            }
+        }
+
+        if self.passthrough && tag.is_none() {
+            return Ok(Some(OutputRow { fields : None, keys : Vec::new() }));
+        }
+
+        let mut fields = ByteRecord::with_capacity(record.as_slice().len(), self.columns.len() + 1);
+        for c in self.columns.iter() {
+            match *c {
+                OutputColumn::Cell(ref c) => fields.push_field(&record[c.index]),
+                OutputColumn::Computed(ref c) => fields.push_field(c.eval(&row)?.to_string().as_bytes())
+            }
+        }
+        if let Some(t) = tag {
+            fields.push_field(t.as_bytes());
+        }
+
+        let mut keys = Vec::with_capacity(self.order_by.len());
+        for (k, _) in self.order_by.iter() {
+            keys.push(
+                match *k {
+                    SortKey::Output(pos) => self.columns[pos].value(&row)?,
+                    SortKey::Column(ref c) => c.value(&row)?.clone()
+                });
+        }
+        Ok(Some(OutputRow { fields : Some(fields), keys }))
+    }
+
+    /// Runs each record through the query, giving what to pass to [`Pipeline::accept`] for
+    /// each. This needs no state of its own, so batches can be run on several threads at once.
+    pub fn run_batch(&self, records:&[ByteRecord], tag:Option<&str>) -> Vec<Result<Option<OutputRow>>> {
+        let mut values = RowValues::default();
+        records.iter().map(|r|{ self.run_row(r, tag, &mut values) }).collect()
+    }
+}
+
+// Rows kept for an ORDER BY with a LIMIT of n are cut back to the best n once there are
+// this many times n of them (or of this many rows, for small limits), so memory use
+// follows the limit rather than the input
+const LIMIT_SLACK : usize = 2;
+const MIN_KEPT : usize = 1024;
+
+/// Runs rows through a query and writes out the results. Rows are buffered when the query
+/// has an ORDER BY, otherwise they are written as soon as they match. With a LIMIT as well,
+/// only the rows that could still be among the first are kept.
+pub struct Pipeline {
+    query : Arc<SelectQuery>,
+    // rows held for ordering, with the order they matched in, which breaks ties
+    buffered : Vec<(usize, OutputRow)>,
+    matched : usize,
+    written : usize,
+    values : RowValues
+}
+
+impl Pipeline {
+    pub fn new(query:SelectQuery) -> Pipeline {
+        Pipeline { query : Arc::new(query), buffered : Vec::new(), matched : 0, written : 0, values : RowValues::default() }
+    }
+
+    /// The query, for running batches of records on other threads
+    pub fn query(&self) -> Arc<SelectQuery> {
+        self.query.clone()
+    }
+
+    /// true once no further rows can change the output
+    pub fn is_done(&self) -> bool {
+        match self.query.limit {
+            Some(n) => self.query.order_by.is_empty() && self.written >= n,
+            None => false
+        }
+    }
+
+    /// Writes out `row` if it matches, or keeps it for [`Pipeline::finish`] when ordering.
+    /// `tag` is appended to every output row if given.
+    pub fn push<W : Write>(&mut self, record:&ByteRecord, tag:Option<&str>, w:&mut Writer<W>) -> Result<()> {
+        if self.is_done() {
+            return Ok(());
+        }
+        let output = self.query.run_row(record, tag, &mut self.values)?;
+        self.accept(record, output, w)
+    }
+
+    /// As push, for a record already run through the query with [`SelectQuery::run_batch`].
+    /// Records have to be accepted in the order they were read.
+    pub fn accept<W : Write>(&mut self, record:&ByteRecord, output:Option<OutputRow>, w:&mut Writer<W>) -> Result<()> {
+        let output =
+            match output {
+                Some(o) if !self.is_done() => o,
+                _ => return Ok(())
+            };
+
+        if self.query.order_by.is_empty() {
+            match output.fields {
+                Some(ref fields) => w.write_byte_record(fields)?,
+                None => w.write_byte_record(record)?
+            }
+            self.written += 1;
+        }
+        else {
+            self.buffered.push((self.matched, output));
+            self.matched += 1;
+            if let Some(n) = self.query.limit {
+                if self.buffered.len() >= LIMIT_SLACK * n.max(MIN_KEPT) {
+                    self.keep_first(n);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Orders two buffered rows by the ORDER BY, then by when they matched
+    fn compare(order_by:&[(SortKey, bool)], a:&(usize, OutputRow), b:&(usize, OutputRow)) -> Ordering {
+        for (i, &(_, descending)) in order_by.iter().enumerate() {
+            let o = a.1.keys[i].compare(&b.1.keys[i]);
+            let o = if descending { o.reverse() } else { o };
+            if o != Ordering::Equal {
+                return o;
+            }
+        }
+        a.0.cmp(&b.0)
+    }
+
+    // Drops all but the first n buffered rows, in no particular order
+    fn keep_first(&mut self, n:usize) {
+        if self.buffered.len() <= n {
+            return;
+        }
+        if n > 0 {
+            let order_by = &self.query.order_by;
+            self.buffered.select_nth_unstable_by(n - 1, |a, b|{ Pipeline::compare(order_by, a, b) });
+        }
+        self.buffered.truncate(n);
+    }
+
+    /// Writes out any rows held back for ordering
+    pub fn finish<W : Write>(mut self, w:&mut Writer<W>) -> Result<()> {
+        if self.buffered.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(n) = self.query.limit {
+            self.keep_first(n);
+        }
+        let order_by = &self.query.order_by;
+        self.buffered.sort_unstable_by(|a, b|{ Pipeline::compare(order_by, a, b) });
+
+        for (_, r) in self.buffered.iter() {
+            // rows are only held back when ordering, which never passes records through
+            if let Some(ref fields) = r.fields {
+                w.write_byte_record(fields)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{ByteRecord, StringRecord, Writer};
+    use rust_decimal::Decimal;
+    use query::{parse_statement, Precedence, QueryFn};
+    use query::query_tree::ArithOp;
+    use query::select::{arith, IntType, NumType, Pipeline, SelectQuery};
+    use schema::Schema;
+    use value::Value;
+
+    fn run(q:&str) -> Result<Vec<String>, String> {
+        let header = vec!("stock[string]", "price[float]", "size[int]", "executed[bool]");
+        let rows = vec!(
+            vec!("VOD.L", "99.96", "100", "true"),
+            vec!("AAPL.O", "101.5", "100", "true"),
+            vec!("VOD.L", "99.96", "100", "false"),
+            vec!("AAPL.O", "102.7", "200", "true"),
+            vec!("MSFT.O", "186.22", "500", "false")
+        );
+        run_on(header, rows, q)
+    }
+
+    // The output lines of `q` run over `rows` of input with `header`
+    fn run_on(header:Vec<&str>, rows:Vec<Vec<&str>>, q:&str) -> Result<Vec<String>, String> {
+        let schema = Schema::from_header(&StringRecord::from(header)).map_err(|e|{ e.to_string() })?;
+        let query = parse_statement(q, &schema, Precedence::Standard).map_err(|e|{ e.to_string() })?;
+        let mut w = Writer::from_writer(Vec::new());
+        w.write_record(query.header()).unwrap();
+        let mut p = Pipeline::new(query);
+        for r in rows {
+            p.push(&StringRecord::from(r).into_byte_record(), None, &mut w).map_err(|e|{ e.to_string() })?;
+        }
+        p.finish(&mut w).map_err(|e|{ e.to_string() })?;
+
+        let out = String::from_utf8(w.into_inner().unwrap()).unwrap();
+        Ok(out.lines().map(|l|{ l.to_owned() }).collect())
+    }
+
+    #[test]
+    fn computed_columns_are_ordered_and_limited() {
+        let actual = run("SELECT stock, price*size AS notional WHERE executed = true ORDER BY notional DESC LIMIT 2").unwrap();
+        assert_eq!(vec!("stock[string],notional[float]", "AAPL.O,20540", "AAPL.O,10150"), actual);
+    }
+
+    #[test]
+    fn limits_on_ordered_rows_give_the_first_of_all_the_ordered_rows() {
+        // enough rows for those kept to be cut back several times, with many ties
+        let owned : Vec<Vec<String>> = (0..5000).map(|i|{ vec!(format!("S{}", i), (i * 7919 % 300).to_string()) }).collect();
+        let rows : Vec<Vec<&str>> = owned.iter().map(|r|{ r.iter().map(|f|{ f.as_str() }).collect() }).collect();
+        let run = |q:&str|{ run_on(vec!("stock[string]", "size[int]"), rows.clone(), q).unwrap() };
+
+        for order in &["size", "size DESC", "size, stock DESC"] {
+            let all = run(&format!("SELECT stock, size ORDER BY {}", order));
+            for &n in &[0, 1, 10, 1500, 3000, 6000] {
+                let limited = run(&format!("SELECT stock, size ORDER BY {} LIMIT {}", order, n));
+                assert_eq!(all.iter().take(n + 1).collect::<Vec<_>>(), limited.iter().collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn star_selects_every_column_unchanged() {
+        let actual = run("SELECT * WHERE size > 200").unwrap();
+        assert_eq!(vec!("stock[string],price[float],size[int],executed[bool]", "MSFT.O,186.22,500,false"), actual);
+    }
+
+    #[test]
+    fn order_by_can_use_unselected_columns_and_several_keys() {
+        let actual = run("select stock AS s order by price desc, stock limit 3").unwrap();
+        assert_eq!(vec!("s[string]", "MSFT.O", "AAPL.O", "AAPL.O"), actual);
+    }
+
+    #[test]
+    fn integer_arithmetic_stays_integer() {
+        let actual = run("SELECT size / 3 + 1 AS x LIMIT 1").unwrap();
+        assert_eq!(vec!("x[int]", "34"), actual);
+    }
+
+    #[test]
+    fn literals_are_constants() {
+        let actual = run("SELECT 'size' AS label, size WHERE stock = \"MSFT.O\"").unwrap();
+        assert_eq!(vec!("label[string],size[int]", "size,500"), actual);
+    }
+
+    #[test]
+    fn bad_cells_name_their_column() {
+        let header = StringRecord::from(vec!("stock[string]", "price[float]"));
+        let schema = Schema::from_header(&header).unwrap();
+        let row = StringRecord::from(vec!("VOD.L", "n/a")).into_byte_record();
+        let mut w = Writer::from_writer(Vec::new());
+        for q in &["SELECT * WHERE price > 1", "SELECT price * 2 AS x", "SELECT stock ORDER BY price"] {
+            let mut p = Pipeline::new(parse_statement(q, &schema, Precedence::Standard).unwrap());
+            let e = p.push(&row, None, &mut w).err().unwrap();
+            assert_eq!("Could not make a float from 'n/a' in column price", e.to_string());
+        }
+    }
+
+    #[test]
+    fn fields_are_written_as_read() {
+        let header = StringRecord::from(vec!("stock[string]", "size[int]"));
+        let schema = Schema::from_header(&header).unwrap();
+        let row = ByteRecord::from(vec!(&b"VOD\xff.L"[..], &b" 100"[..]));
+        for q in &["SELECT * WHERE size > 1", "SELECT size, stock WHERE size > 1"] {
+            let mut w = Writer::from_writer(Vec::new());
+            let mut p = Pipeline::new(parse_statement(q, &schema, Precedence::Standard).unwrap());
+            p.push(&row, None, &mut w).unwrap();
+            let out = w.into_inner().unwrap();
+            assert!(out.windows(5).any(|f|{ f == b"VOD\xff." }));
+            assert!(out.windows(4).any(|f|{ f == b" 100" }));
+        }
+    }
+
+    #[test]
+    fn queries_can_be_shared_between_threads() {
+        fn shareable<T : Send + Sync>() {}
+        shareable::<QueryFn>();
+        shareable::<SelectQuery>();
+    }
+
+    #[test]
+    fn bad_statements_are_rejected() {
+        assert!(run("SELECT price*size").is_err());
+        assert!(run("SELECT stock*size AS x").is_err());
+        assert!(run("SELECT volume").is_err());
+        assert!(run("SELECT 'a' + size AS x").is_err());
+        assert!(run("SELECT stock ORDER BY volume").is_err());
+    }
+
+    #[test]
+    fn timestamps_compare_as_instants_and_keep_their_format() {
+        let header = vec!("stock[string]", "exec_time[timestamp:%Y%m%d-%H:%M:%S%.f]", "trade_date[date]");
+        let rows = vec!(
+            vec!("VOD.L", "20261018-08:59:59.999", "2026-10-18"),
+            vec!("AAPL.O", "20261018-09:00:00", "2026-10-18"),
+            vec!("MSFT.O", "20261018-09:30:00.5", "2026-10-18"),
+            vec!("BP.L", "20261019-09:30:00", "2026-10-19")
+        );
+        let actual = run_on(header, rows, "SELECT exec_time, stock WHERE exec_time >= 2026-10-18T10:00:00+01:00 && trade_date = 2026-10-18 ORDER BY exec_time DESC").unwrap();
+        assert_eq!(vec!("exec_time[timestamp:%Y%m%d-%H:%M:%S%.f],stock[string]", "20261018-09:30:00.5,MSFT.O", "20261018-09:00:00,AAPL.O"), actual);
+    }
+
+    #[test]
+    fn integer_results_are_wide_enough_for_either_side() {
+        let t = |name|{ IntType::from_name(name).unwrap() };
+        assert_eq!(t("u8"), IntType::combine(t("u8"), t("u8")));
+        assert_eq!(t("int"), IntType::combine(t("i8"), t("i64")));
+        assert_eq!(t("i16"), IntType::combine(t("i8"), t("u8")));
+        assert_eq!(t("i128"), IntType::combine(t("int"), t("u64")));
+        assert_eq!(t("i128"), IntType::combine(t("i8"), t("u128")));
+        assert_eq!("int", t("i64").name());
+        assert!(IntType::from_name("i7").is_none());
+    }
+
+    #[test]
+    fn integer_overflow_is_an_error() {
+        let int = |name|{ NumType::Int(IntType::from_name(name).unwrap()) };
+        assert_eq!(Value::UInt(255), arith(ArithOp::Add, Value::UInt(200), Value::UInt(55), int("u8")).unwrap());
+        assert_eq!(Value::Int(-56), arith(ArithOp::Sub, Value::Int(144), Value::UInt(200), int("i16")).unwrap());
+        let overflows = [
+            (ArithOp::Add, Value::UInt(200), Value::UInt(200), "u8", "Integer overflow in 200 + 200"),
+            (ArithOp::Sub, Value::UInt(1), Value::UInt(2), "u8", "Integer overflow in 1 - 2"),
+            (ArithOp::Mul, Value::Int(-128), Value::Int(-1), "i8", "Integer overflow in -128 * -1"),
+            (ArithOp::Add, Value::UInt(u128::MAX), Value::UInt(1), "u128", "Integer overflow in 340282366920938463463374607431768211455 + 1"),
+            (ArithOp::Div, Value::Int(1), Value::Int(0), "int", "Division by zero in 1 / 0")
+        ];
+        for (op, a, b, t, expected) in overflows {
+            assert_eq!(expected, arith(op, a, b, int(t)).err().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn integer_columns_give_results_of_their_types() {
+        let header = vec!("qty[long]", "small[u8]", "ns[u64]");
+        let rows = vec!(vec!("3000000000", "200", "18446744073709551615"));
+        let run = |q|{ run_on(header.clone(), rows.clone(), q) };
+        assert_eq!(vec!("x[int]", "6000000000"), run("SELECT qty * 2 AS x").unwrap());
+        assert_eq!(vec!("x[u8]", "1"), run("SELECT small / small AS x").unwrap());
+        assert_eq!(vec!("x[int]", "-56"), run("SELECT 144 - small AS x").unwrap());
+        assert_eq!(vec!("x[i128]", "18446744073709551616"), run("SELECT ns + 1 AS x").unwrap());
+        assert_eq!("Integer overflow in 200 + 200", run("SELECT small + small AS x").err().unwrap());
+        assert_eq!(2, run("SELECT * WHERE ns > 18446744073709551614 && qty > 2147483647").unwrap().len());
+    }
+
+    #[test]
+    fn negative_constants_compare_with_signed_columns() {
+        let header = vec!("stock[string]", "change[i8]");
+        let rows = vec!(vec!("VOD.L", "-128"), vec!("BP.L", "-3"), vec!("AAPL.O", "7"));
+        let run = |q|{ run_on(header.clone(), rows.clone(), q) };
+        assert_eq!(vec!("stock[string]", "BP.L", "AAPL.O"), run("SELECT stock WHERE change > -5").unwrap());
+        assert_eq!(vec!("stock[string]", "VOD.L"), run("SELECT stock WHERE change = -128").unwrap());
+        assert_eq!(vec!("x[int]", "-123", "2", "12"), run("SELECT change - -5 AS x").unwrap());
+        assert!(run("SELECT stock WHERE change = -129").err().unwrap().contains("Could not make a i8 from '-129'"));
+    }
+
+    #[test]
+    fn decimal_arithmetic_is_exact() {
+        let d = |s:&str|{ Value::Decimal(s.parse::<Decimal>().unwrap()) };
+        assert_eq!(d("0.3"), arith(ArithOp::Sub, d("0.9"), d("0.6"), NumType::Decimal).unwrap());
+        assert_eq!(d("558.66"), arith(ArithOp::Mul, d("186.22"), Value::Int(3), NumType::Decimal).unwrap());
+        assert_eq!("Division by zero in 1 / 0", arith(ArithOp::Div, d("1"), d("0"), NumType::Decimal).err().unwrap().to_string());
+        assert_eq!("Decimal overflow in 79228162514264337593543950335 + 1", arith(ArithOp::Add, Value::Decimal(Decimal::MAX), Value::Int(1), NumType::Decimal).err().unwrap().to_string());
+        // a float on either side makes the result a float
+        assert_eq!(NumType::Float, NumType::combine(NumType::Decimal, NumType::Float));
+        assert_eq!(NumType::Decimal, NumType::combine(NumType::Int(IntType::from_name("u8").unwrap()), NumType::Decimal));
+    }
+
+    #[test]
+    fn decimal_columns_compare_and_compute_exactly() {
+        let header = vec!("price[decimal(6,2)]", "size[int]", "fx[float]");
+        let rows = vec!(vec!("0.30", "3", "0.5"), vec!("186.22", "3", "0.5"));
+        let run = |q|{ run_on(header.clone(), rows.clone(), q) };
+        assert_eq!(vec!("x[decimal]", "0.30"), run("SELECT price * 3 - 0.6 AS x WHERE price = 0.3").unwrap());
+        assert_eq!(vec!("x[decimal]", "558.66"), run("SELECT price * size AS x WHERE price > 186.21 && price < 186.23").unwrap());
+        assert_eq!(vec!("x[float]", "0.15", "93.11"), run("SELECT price * fx AS x").unwrap());
+    }
+
+    #[test]
+    fn nulls_are_kept_through_selects_and_sort_first() {
+        let header = vec!("stock[string]", "size[int?]", "price[float?]");
+        let rows = vec!(vec!("VOD.L", "100", "99.5"), vec!("BP.L", "", ""), vec!("AAPL.O", "", "101.5"), vec!("MSFT.O", "300", ""));
+        let run = |q|{ run_on(header.clone(), rows.clone(), q) };
+        assert_eq!(vec!("stock[string]", "BP.L", "AAPL.O"), run("SELECT stock WHERE size is null").unwrap());
+        assert_eq!(vec!("stock[string]", "AAPL.O"), run("SELECT stock WHERE size IS NULL AND price IS NOT NULL").unwrap());
+        assert_eq!(vec!("stock[string],x[float?]", "MSFT.O,", "BP.L,", "AAPL.O,", "VOD.L,9950"), run("SELECT stock, size * price AS x ORDER BY x, size DESC").unwrap());
+        assert_eq!(vec!("stock[string],x[int]", "VOD.L,200"), run("SELECT stock, 2 * 100 AS x LIMIT 1").unwrap());
+    }
+
+    #[test]
+    fn enums_compare_in_declared_order() {
+        let header = vec!("side[enum(BUY,SELL)]", "venue[enum(LSE, XETRA, NYSE)?]", "other[enum(BUY,SELL)]", "prio[enum(SELL,BUY)]");
+        let rows = vec!(vec!("SELL", "NYSE", "BUY", "BUY"), vec!("BUY", "LSE", "BUY", "BUY"), vec!("SELL", "", "SELL", "BUY"));
+        let run = |q|{ run_on(header.clone(), rows.clone(), q) };
+        // the headers are quoted, for the commas in them
+        assert_eq!(vec!("\"side[enum(BUY,SELL)]\",\"venue[enum(LSE, XETRA, NYSE)?]\"", "SELL,NYSE"), run("SELECT side, venue WHERE side = SELL && venue > XETRA").unwrap());
+        assert_eq!(vec!("\"venue[enum(LSE, XETRA, NYSE)?]\"", "\"\"", "LSE", "NYSE"), run("SELECT venue ORDER BY venue").unwrap());
+        assert_eq!(vec!("\"side[enum(BUY,SELL)]\"", "BUY", "SELL"), run("SELECT side WHERE side = other").unwrap());
+        assert!(run("SELECT side WHERE side = prio").err().unwrap().starts_with("Can't apply = to enum(BUY,SELL) and enum(SELL,BUY)"));
+        assert!(run("SELECT side WHERE side = HOLD").err().unwrap().contains("Could not make a enum(BUY,SELL) from 'HOLD'"));
+    }
+}
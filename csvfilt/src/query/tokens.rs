@@ -1,9 +1,60 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::slice::Iter;
+use std::str::Chars;
+
+use query::parse_error::{ErrorKind, ParseError};
+
+/// Byte offsets into the query, `end` exclusive
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start : usize,
+    pub end : usize
+}
+
+impl Span {
+    // From the start of this span to the end of `other`
+    pub fn to(self, other:Span) -> Span {
+        Span { start : self.start, end : other.end }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token : Token,
+    pub span : Span
+}
+
+pub type TokenStream<'a> = Peekable<Iter<'a, SpannedToken>>;
+
+// The next token, without its span
+pub fn peek<'a>(p : &mut TokenStream<'a>) -> Option<&'a Token> {
+    p.peek().map(|&t|{ &t.token })
+}
+
+// A '-' written directly before a number, as in `size > -5`, read as a negative number. The
+// tokeniser leaves '-' as a token of its own, as in SELECT it can also be a subtraction.
+pub fn negative_number(p : &mut TokenStream) -> Option<(String, Span)> {
+    let mut ahead = p.clone();
+    let (minus, number) = (ahead.next()?, ahead.next()?);
+    match (&minus.token, &number.token) {
+        (&Token::Minus, Token::ConstOrIdentifier(n))
+            if minus.span.end == number.span.start && n.starts_with(|c:char|{ c.is_ascii_digit() }) =>
+            {
+                *p = ahead;
+                Some((format!("-{}", n), minus.span.to(number.span)))
+            }
+        _ => None
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     OpenBracket,
     CloseBracket,
     ConstOrIdentifier(String),
+    Literal(String),
+    Column(String),
     Eq,
     Not,
     NotEq,
@@ -12,13 +63,68 @@ pub enum Token {
     Gt,
     GEq,
     And,
-    Or
+    Or,
+    Comma,
+    Star,
+    Plus,
+    Minus,
+    Slash
 }
 
-use std::str::Chars;
-use std::iter::Peekable;
+// How a token is shown in error messages, close to how it was written
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::ConstOrIdentifier(ref s) => write!(f, "'{}'", s),
+            Token::Literal(ref s) => write!(f, "{:?}", s),
+            Token::Column(ref s) => write!(f, "`{}`", s),
+            Token::OpenBracket => write!(f, "'('"),
+            Token::CloseBracket => write!(f, "')'"),
+            Token::Eq => write!(f, "'='"),
+            Token::Not => write!(f, "'!'"),
+            Token::NotEq => write!(f, "'!='"),
+            Token::Lt => write!(f, "'<'"),
+            Token::LEq => write!(f, "'<='"),
+            Token::Gt => write!(f, "'>'"),
+            Token::GEq => write!(f, "'>='"),
+            Token::And => write!(f, "'&&'"),
+            Token::Or => write!(f, "'||'"),
+            Token::Comma => write!(f, "','"),
+            Token::Star => write!(f, "'*'"),
+            Token::Plus => write!(f, "'+'"),
+            Token::Minus => write!(f, "'-'"),
+            Token::Slash => write!(f, "'/'")
+        }
+    }
+}
+
+// The characters of a query, keeping count of how far into it they have got, in bytes
+struct CharStream<'a> {
+    chars : Peekable<Chars<'a>>,
+    offset : usize
+}
+
+impl<'a> CharStream<'a> {
+    fn new(s: &'a str) -> CharStream<'a> {
+        CharStream { chars : s.chars().peekable(), offset : 0 }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+}
 
-fn consume_while<F>(it: &mut Peekable<Chars>, x: F) -> Vec<char>
+impl<'a> Iterator for CharStream<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+}
+
+fn consume_while<F>(it: &mut CharStream, x: F) -> Vec<char>
     where F : Fn(char) -> bool {
  
     let mut v: Vec<char> = vec![];
@@ -34,121 +140,182 @@ fn consume_while<F>(it: &mut Peekable<Chars>, x: F) -> Vec<char>
     v
 }
 
-use std::error::Error;
+// Reads a string literal up to the closing `quote`, the opening quote having been consumed
+fn quoted(it: &mut CharStream, quote: char) -> Result<String, String> {
+    let mut s = String::new();
+    loop {
+        match it.next() {
+            None => return Err(format!("Unterminated string literal, expected closing {}", quote)),
+            Some(c) if c == quote => return Ok(s),
+            Some('\\') => {
+                let escaped =
+                    match it.next() {
+                        Some('\\') => '\\',
+                        Some('\'') => '\'',
+                        Some('"') => '"',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('u') => unicode_escape(it)?,
+                        Some(other) => return Err(format!("Unknown escape sequence '\\{}'", other)),
+                        None => return Err("Unterminated escape sequence at end of query".to_owned())
+                    };
+                s.push(escaped);
+            }
+            Some(c) => s.push(c)
+        }
+    }
+}
 
-pub fn tokenise(s : &String) -> Result<Vec<Token>, Box<Error>> {
-    let mut char_stream = s.chars().peekable();
+// A column named explicitly, either `any text` or $ident, the leading ` or $ having been consumed
+fn column(it: &mut CharStream, quote: char) -> Result<String, String> {
+    use std::iter::FromIterator;
+    let name =
+        if quote == '`' {
+            let name = String::from_iter(consume_while(it, |c|{ c != '`' }));
+            if it.next().is_none() {
+                return Err(format!("Unterminated column name, expected closing ` after `{}", name));
+            }
+            name
+        }
+        else {
+            String::from_iter(consume_while(it, is_allowed_in_identifier))
+        };
+    if name.is_empty() {
+        return Err(format!("Expected a column name after {}", quote));
+    }
+    Ok(name)
+}
+
+fn is_allowed_in_identifier(c:char) -> bool
+{
+    c.is_alphanumeric() || c == '.' || c == '_'
+}
+
+// \u{XXXX}, the leading \u having been consumed
+fn unicode_escape(it: &mut CharStream) -> Result<char, String> {
+    if it.next() != Some('{') {
+        return Err("Expected '{' after \\u".to_owned());
+    }
+    let hex : String = consume_while(it, |c|{ c.is_ascii_hexdigit() }).into_iter().collect();
+    if it.next() != Some('}') {
+        return Err(format!("Expected '}}' to close \\u{{{}", hex));
+    }
+    u32::from_str_radix(&hex, 16).ok()
+        .and_then(::std::char::from_u32)
+        .ok_or_else(||{ format!("'\\u{{{}}}' is not a valid character", hex) })
+}
+
+// An ISO-8601 date, time or timestamp at the start of `s`, e.g. 2026-10-18T09:00:00Z, which
+// would otherwise read as a subtraction or have characters no other token uses
+fn date_time_literal(s: &str) -> Option<&str> {
+    use regex::Regex;
+    lazy_static! {
+        static ref DATE_TIME : Regex = Regex::new(
+            r"^(\d{4}-\d{2}-\d{2}(T\d{2}:\d{2}(:\d{2}(\.\d+)?)?(Z|[+-]\d{2}:?\d{2})?)?|\d{2}:\d{2}(:\d{2}(\.\d+)?)?)").unwrap();
+    }
+    let m = DATE_TIME.find(s)?.as_str();
+    match s[m.len()..].chars().next() {
+        Some(c) if is_allowed_in_identifier(c) => None,
+        _ => Some(m)
+    }
+}
+
+// `c` followed by `=` makes `with_eq`, otherwise `c` alone is `without`
+fn maybe_eq(it: &mut CharStream, with_eq: Token, without: Token) -> Token {
+    if it.peek() == Some(&'=') {
+        it.next().unwrap();
+        with_eq
+    }
+    else {
+        without
+    }
+}
+
+// `c` has to be doubled, as in && and ||
+fn doubled(it: &mut CharStream, c: char, token: Token) -> Result<Token, String> {
+    match it.next() {
+        Some(next) if next == c => Ok(token),
+        Some(other) => Err(format!("expected '{}{}' - found '{}{}'", c, c, c, other)),
+        None => Err(format!("expected '{}{}' - found '{}<EOF>'", c, c, c))
+    }
+}
+
+// Reads the token starting at the next, non-whitespace, character
+fn next_token(it: &mut CharStream) -> Result<Token, String> {
+    let c = *it.peek().unwrap();
+    if is_allowed_in_identifier(c) {
+        use std::iter::FromIterator;
+        let s = String::from_iter(consume_while(it, is_allowed_in_identifier));
+        return Ok(Token::ConstOrIdentifier(s));
+    }
+
+    it.next().unwrap();
+    match c {
+        '(' => Ok(Token::OpenBracket),
+        ')' => Ok(Token::CloseBracket),
+        ',' => Ok(Token::Comma),
+        '*' => Ok(Token::Star),
+        '+' => Ok(Token::Plus),
+        '-' => Ok(Token::Minus),
+        '/' => Ok(Token::Slash),
+        '=' => Ok(Token::Eq),
+        '"' | '\'' => Ok(Token::Literal(quoted(it, c)?)),
+        '`' | '$' => Ok(Token::Column(column(it, c)?)),
+        '<' => Ok(maybe_eq(it, Token::LEq, Token::Lt)),
+        '>' => Ok(maybe_eq(it, Token::GEq, Token::Gt)),
+        '!' => Ok(maybe_eq(it, Token::NotEq, Token::Not)),
+        '&' => doubled(it, '&', Token::And),
+        '|' => doubled(it, '|', Token::Or),
+        x => Err(format!("Unexpected character '{}'", x))
+    }
+}
+
+pub fn tokenise(s : &str) -> Result<Vec<SpannedToken>, ParseError> {
+    let mut char_stream = CharStream::new(s);
 
     let mut tokens = Vec::new();
 
     loop {
-        match char_stream.peek() {
-            None => break,
-            Some (&c) =>
-                match c {
-                    '(' => {
-                        char_stream.next().unwrap();
-                        tokens.push(Token::OpenBracket);
-                    }
-                    ')' => {
-                        char_stream.next().unwrap();
-                        tokens.push(Token::CloseBracket);
-                    }
-                    '<' => {
-                        char_stream.next().unwrap();
-                        match char_stream.peek() {
-                            Some(&'=') => {
-                                char_stream.next().unwrap();
-                                tokens.push(Token::LEq)
-                            }
-                            _ =>
-                                tokens.push(Token::Lt)              
-                        }
-                    }
-                    '=' => {
-                        char_stream.next().unwrap();
-                        tokens.push(Token::Eq)
-                    }
-                    '>' => {
-                        char_stream.next().unwrap();
-                        match char_stream.peek() {
-                            Some(&'=') => {
-                                char_stream.next().unwrap();
-                                tokens.push(Token::GEq)
-                            }
-                            _ =>
-                                tokens.push(Token::Gt)              
-                        }
-                    }
-                    '!' => {
-                        char_stream.next().unwrap();
-                        match char_stream.peek() {
-                            Some(&'=') => {
-                                char_stream.next().unwrap();
-                                tokens.push(Token::NotEq)
-                            }
-                            _ =>
-                                tokens.push(Token::Not)              
-                        }
-                    }
-                    '&' => {
-                        char_stream.next().unwrap();
-                        match char_stream.peek() {
-                            Some(&'&') => {
-                                char_stream.next().unwrap();
-                                tokens.push(Token::And)
-                            }
-                            Some(&other) => {
-                                return Err(From::from(format!("expected '&&' - found '&{}'", other)))
-                            }
-                            None => {
-                                return Err(From::from("expected '&&' - found '&<EOF>'"))                                
-                            }
-                        }
-                    }
-                    '|' => {
-                        char_stream.next().unwrap();
-                        match char_stream.peek() {
-                            Some(&'|') => {
-                                char_stream.next().unwrap();
-                                tokens.push(Token::Or)
-                            }
-                            Some(&other) => {
-                                return Err(From::from(format!("expected '||' - found '|{}'", other)))
-                            }
-                            None => {
-                                return Err(From::from("expected '||' - found '|<EOF>'"))                                
-                            }
-                        }
-                    }
-                    x => {
-                        fn is_allowed_in_identifier(c:char) -> bool
-                        {
-                            c.is_alphanumeric() || c == '.' // TODO think here about what is valid
-                        }
-
-                        if x.is_whitespace() {
-                            // consume, don't use
-                            char_stream.next().unwrap();                        
-                        }
-                        else if is_allowed_in_identifier(x) { 
-                            use std::iter::FromIterator;
-                            let s = String::from_iter(consume_while(&mut char_stream, is_allowed_in_identifier));
-                            tokens.push(Token::ConstOrIdentifier(s));
-                        }
-                        else {
-                            return Err(From::from(format!("Unexpected token {}", x)))
-                        }
-                    }
-                }
+        consume_while(&mut char_stream, char::is_whitespace);
+        let start = char_stream.offset;
+        if char_stream.peek().is_none() {
+            break;
+        }
+
+        if let Some(literal) = date_time_literal(&s[start..]) {
+            for _ in 0..literal.chars().count() {
+                char_stream.next();
+            }
+            let span = Span { start, end : start + literal.len() };
+            tokens.push(SpannedToken { token : Token::ConstOrIdentifier(literal.to_owned()), span });
+            continue;
+        }
+
+        match next_token(&mut char_stream) {
+            Ok(token) => {
+                let span = Span { start, end : char_stream.offset };
+                tokens.push(SpannedToken { token, span });
+            }
+            Err(e) => {
+                let span = Span { start, end : char_stream.offset };
+                return Err(ParseError::new(ErrorKind::Tokenise(e), Some(span)));
+            }
         }
     }
     Ok(tokens)
 }
 
+#[cfg(test)]
 mod tests {
 
-    use query::tokens::{tokenise, Token};
+    use query::tokens::{tokenise, Span, Token};
+
+    // just the tokens, without their spans
+    fn tokens(s:&str) -> Vec<Token> {
+        tokenise(s).unwrap().into_iter().map(|t|{ t.token }).collect()
+    }
 
     lazy_static! {
         static ref ALL_TOKENS : Vec<Token> = vec!(
@@ -163,15 +330,22 @@ mod tests {
             Token::Not,
             Token::And,
             Token::Or,
-            Token::ConstOrIdentifier("abc".to_owned())
+            Token::Comma,
+            Token::Star,
+            Token::Plus,
+            Token::Minus,
+            Token::Slash,
+            Token::ConstOrIdentifier("abc".to_owned()),
+            Token::Literal("d e".to_owned()),
+            Token::Column("f g".to_owned())
         );
     }
 
     #[test]
     fn tokenise_recognises_all_chars() {
-        let s = "()=<<=>>=!=!&&||abc".to_owned();
+        let s = "()=<<=>>=!=!&&||,*+-/abc'd e'`f g`".to_owned();
 
-        let actual = tokenise(&s).unwrap();
+        let actual = tokens(&s);
 
         for (e,a) in ALL_TOKENS.iter().zip(actual.iter())
         {
@@ -182,9 +356,9 @@ mod tests {
 
     #[test]
     fn whitespace_doesnt_matter() {
-        let s = "( ) = < <= > >= != ! && || abc".to_owned();
+        let s = "( ) = < <= > >= != ! && || , * + - / abc 'd e' `f g`".to_owned();
 
-        let actual = tokenise(&s).unwrap();
+        let actual = tokens(&s);
 
         for (e,a) in ALL_TOKENS.iter().zip(actual.iter())
         {
@@ -197,12 +371,10 @@ mod tests {
         let s = "abc def".to_owned();
 
         let expected = 
-            vec!(
-                Token::ConstOrIdentifier("abc".to_owned()),
-                Token::ConstOrIdentifier("def".to_owned())
-            );
+            [Token::ConstOrIdentifier("abc".to_owned()),
+                Token::ConstOrIdentifier("def".to_owned())];
 
-        let actual = tokenise(&s).unwrap();
+        let actual = tokens(&s);
 
         for (e,a) in expected.iter().zip(actual.iter())
         {
@@ -210,6 +382,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dates_and_times_are_single_constants() {
+        let s = "ts>=2026-10-18T09:00:00Z&&t<09:30:00.5&&d=2026-10-18&&z<2026-10-18T10:00+01:00&&x=2026-10".to_owned();
+
+        let constants : Vec<Token> =
+            tokens(&s).into_iter().filter(|t|{ matches!(*t, Token::ConstOrIdentifier(_)) }).collect();
+
+        let expected : Vec<Token> =
+            ["ts", "2026-10-18T09:00:00Z", "t", "09:30:00.5", "d", "2026-10-18", "z", "2026-10-18T10:00+01:00", "x", "2026", "10"]
+                .iter().map(|c|{ Token::ConstOrIdentifier(c.to_string()) }).collect();
+        assert_eq!(expected, constants);
+    }
+
     #[test]
     fn test_not_vs_noteq() {
         let s = "!a!b!!=c!=!d".to_owned();
@@ -226,7 +411,7 @@ mod tests {
                 Token::Not,               
                 Token::ConstOrIdentifier("d".to_owned())
             );
-        let actual = tokenise(&s).unwrap();
+        let actual = tokens(&s);
 
         for (e,a) in expected.iter().zip(actual.iter())
         {
@@ -238,38 +423,84 @@ mod tests {
     fn incomplete_and_causes_error() {
         let s1 = "abc & def".to_owned();
 
-        match tokenise(&s1) {
-            Ok(_) =>
-                panic!("Expected failure, got success"),
-            _ => ()
-        }
+        assert!(tokenise(&s1).is_err(), "Expected failure, got success");
 
         let s2 = "&".to_owned();
 
-        match tokenise(&s2) {
-            Ok(_) =>
-                panic!("Expected failure, got success"),
-            _ => ()
-        }
+        assert!(tokenise(&s2).is_err(), "Expected failure, got success");
     }
 
     #[test]
     fn incomplete_or_causes_error() {
         let s1 = "abc | def".to_owned();
 
-        match tokenise(&s1) {
-            Ok(_) =>
-                panic!("Expected failure, got success"),
-            _ => ()
-            
-        }
+        assert!(tokenise(&s1).is_err(), "Expected failure, got success");
 
         let s2 = "|".to_owned();
 
-        match tokenise(&s2) {
-            Ok(_) =>
-                panic!("Expected failure, got success"),
-            _ => ()
+        assert!(tokenise(&s2).is_err(), "Expected failure, got success");
+    }
+
+    #[test]
+    fn quoted_literals_keep_their_contents() {
+        let s = r#"stock = "BRK B" || name = 'O\'Neil' || d = "2017-10-01 09:00:00" || x = "a&&b""#.to_owned();
+
+        let literals : Vec<Token> =
+            tokens(&s).into_iter().filter(|t|{ matches!(*t, Token::Literal(_)) }).collect();
+
+        assert_eq!(
+            vec!(
+                Token::Literal("BRK B".to_owned()),
+                Token::Literal("O'Neil".to_owned()),
+                Token::Literal("2017-10-01 09:00:00".to_owned()),
+                Token::Literal("a&&b".to_owned())
+            ),
+            literals);
+    }
+
+    #[test]
+    fn escape_sequences_are_decoded() {
+        let s = r#""\\ \" \' \n \t \r \0 \u{e9}""#.to_owned();
+
+        assert_eq!(vec!(Token::Literal("\\ \" ' \n \t \r \0 \u{e9}".to_owned())), tokens(&s));
+    }
+
+    #[test]
+    fn bad_literals_cause_errors() {
+        for s in ["\"abc", "'abc\\'", "'\\q'", "'\\u{110000}'", "'\\u{41'"].iter() {
+            assert!(tokenise(s).is_err(), "{} should not tokenise", s);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn columns_can_be_named_explicitly() {
+        let s = "`my col` = $size_2 && trade_date = `a=b`".to_owned();
+
+        assert_eq!(
+            vec!(
+                Token::Column("my col".to_owned()),
+                Token::Eq,
+                Token::Column("size_2".to_owned()),
+                Token::And,
+                Token::ConstOrIdentifier("trade_date".to_owned()),
+                Token::Eq,
+                Token::Column("a=b".to_owned())
+            ),
+            tokens(&s));
+
+        for s in ["`abc", "$", "$ abc", "``"].iter() {
+            assert!(tokenise(s).is_err(), "{} should not tokenise", s);
+        }
+    }
+
+    #[test]
+    fn tokens_know_where_they_came_from() {
+        let spans : Vec<Span> = tokenise("price >= 'a b'").unwrap().into_iter().map(|t|{ t.span }).collect();
+        assert_eq!(
+            vec!(Span { start : 0, end : 5 }, Span { start : 6, end : 8 }, Span { start : 9, end : 14 }),
+            spans);
+
+        let e = tokenise("a = 'é\\q'").err().unwrap();
+        assert_eq!(Some(Span { start : 4, end : 9 }), e.span);
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+use csv::{ByteRecord, StringRecord, Writer, WriterBuilder};
+
+use csvfilt::{CsvFiltError, Result};
+
+// What to do with a row whose cells can't be read as their types or that has the wrong
+// number of fields
+#[derive(Debug, PartialEq, Clone)]
+pub enum OnError {
+    Fail,
+    Skip,
+    // write the row to a CSV file, with the reason in an extra column
+    Reject(PathBuf)
+}
+
+impl OnError {
+    pub fn from_arg(arg:&str) -> Result<OnError> {
+        match arg {
+            "fail" => Ok(OnError::Fail),
+            "skip" => Ok(OnError::Skip),
+            _ if arg.starts_with("reject:") && arg.len() > "reject:".len() =>
+                Ok(OnError::Reject(PathBuf::from(&arg["reject:".len()..]))),
+            x => Err(CsvFiltError::Usage(format!("Unknown --on-error policy '{}', expected fail, skip or reject:<path>", x)))
+        }
+    }
+}
+
+// Applies an OnError policy to the rows of a run, counting the ones it lets through
+pub struct BadRows {
+    on_error : OnError,
+    rejects : Option<Writer<Box<dyn Write>>>,
+    count : u64
+}
+
+impl BadRows {
+    pub fn new(on_error:OnError, delimiter:u8) -> Result<BadRows> {
+        let rejects =
+            match on_error {
+                OnError::Reject(ref p) => {
+                    let f = File::create(p)
+                        .map_err(|e|{ CsvFiltError::io(format!("Could not create '{}'", p.display()), e) })?;
+                    let out : Box<dyn Write> = Box::new(io::BufWriter::new(f));
+                    // rejected rows keep however many fields they had
+                    Some(WriterBuilder::new().delimiter(delimiter).flexible(true).from_writer(out))
+                }
+                _ => None
+            };
+        Ok(BadRows { on_error, rejects, count : 0 })
+    }
+
+    // The header of the reject file, which is the input's with the reason added
+    pub fn write_header(&mut self, header:&StringRecord) -> Result<()> {
+        if let Some(ref mut w) = self.rejects {
+            w.write_record(header.iter().chain(Some("reason[string]")))?;
+        }
+        Ok(())
+    }
+
+    // Deals with an error from processing `row`, returning it if the run has to stop
+    pub fn handle(&mut self, row:&ByteRecord, e:CsvFiltError) -> Result<()> {
+        if self.on_error == OnError::Fail || !e.is_bad_row() {
+            return Err(e);
+        }
+        if let Some(ref mut w) = self.rejects {
+            let reason = e.to_string();
+            w.write_record(row.iter().chain(Some(reason.as_bytes())))?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    // Flushes the reject file and reports how many rows were left out
+    pub fn finish(self) -> Result<()> {
+        let summary =
+            match self.on_error {
+                OnError::Fail => return Ok(()),
+                OnError::Skip => format!("skipped {} bad rows", self.count),
+                OnError::Reject(ref p) => format!("wrote {} bad rows to '{}'", self.count, p.display())
+            };
+        if let Some(w) = self.rejects {
+            let write_error = |e|{ CsvFiltError::io("Could not write rejected rows".to_owned(), e) };
+            w.into_inner().map_err(|e|{ write_error(e.into_error()) })?.flush().map_err(write_error)?;
+        }
+        eprintln!("csvfilt: {}", summary);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use csv::StringRecord;
+
+    use csvfilt::CsvFiltError;
+    use rejects::{BadRows, OnError};
+
+    #[test]
+    fn policies_are_parsed() {
+        assert_eq!(OnError::Fail, OnError::from_arg("fail").unwrap());
+        assert_eq!(OnError::Skip, OnError::from_arg("skip").unwrap());
+        assert_eq!(OnError::Reject(PathBuf::from("bad.csv")), OnError::from_arg("reject:bad.csv").unwrap());
+        assert!(OnError::from_arg("reject:").is_err());
+        assert!(OnError::from_arg("ignore").is_err());
+    }
+
+    #[test]
+    fn only_bad_rows_are_skipped() {
+        let row = StringRecord::from(vec!("VOD.L", "n/a")).into_byte_record();
+        let cell = ||{ CsvFiltError::CellParse { column : None, record : None, value : "n/a".to_owned(), type_name : "float".to_owned() } };
+
+        let mut skip = BadRows::new(OnError::Skip, b',').unwrap();
+        assert!(skip.handle(&row, cell()).is_ok());
+        assert!(skip.handle(&row, CsvFiltError::Arithmetic("Division by zero".to_owned())).is_err());
+        assert_eq!(1, skip.count);
+
+        let mut fail = BadRows::new(OnError::Fail, b',').unwrap();
+        assert!(fail.handle(&row, cell()).is_err());
+    }
+}
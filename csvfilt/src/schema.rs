@@ -1,44 +1,71 @@
-use std::error::Error;
-
+use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use rust_decimal::Decimal;
+
+use csv::StringRecord;
+
+use error::{CsvFiltError, Result};
+use value::Value;
 
 pub trait SupportedColType : FromStr
 {
     fn str_type() -> String;
 
-    fn parse_err(value:&String) -> Box<Error> {
-        From::from(
-            format!(
-                "Could not make a {} from '{}'",
-                Self::str_type(),
-                value
-            )
-        )
+    fn into_value(self) -> Value;
+
+    fn parse_err(value:&str) -> CsvFiltError {
+        CsvFiltError::CellParse { column : None, record : None, value : value.to_owned(), type_name : Self::str_type() }
+    }
+
+    // A cell, or a constant from the query, read as this type
+    fn make_value(s:&str) -> Result<Value> {
+        s.parse::<Self>().map(Self::into_value).map_err(|_|{ Self::parse_err(s) })
+    }
+
+    // Reads cells written in the format given in the header, as in `ts[timestamp:%Y%m%d]`.
+    // Constants in the query are still read by make_value.
+    fn formatted(format:&str) -> Result<ValueMaker> {
+        Err(CsvFiltError::Schema(format!("type '{}' does not take a format, found '{}'", Self::str_type(), format)))
+    }
+
+    // Reads cells of a type given parameters in the header, as in `price[decimal(10,2)]`
+    fn with_params(params:&str) -> Result<ValueMaker> {
+        Err(CsvFiltError::Schema(format!("type '{}' does not take parameters, found '({})'", Self::str_type(), params)))
+    }
+
+    fn unsupported(op:&str) -> CsvFiltError {
+        CsvFiltError::TypeMismatch { op : op.to_owned(), left : Self::str_type(), right : Self::str_type() }
     }
 }
 
-pub type OpDouble = Result<Box<Fn(&String, &String) -> Result<bool, Box<Error>>>, Box<Error>>;
-pub type OpSingle = Result<Box<Fn(&String) -> Result<bool, Box<Error>>>, Box<Error>>;
+// Comparisons work on values already read from the row, so each cell is parsed at most
+// once however many comparisons use it. Both sides are always of the column's type. They
+// are Send + Sync so a compiled query can be shared by threads evaluating rows.
+pub type OpDouble = Result<Box<dyn Fn(&Value, &Value) -> bool + Send + Sync>>;
+pub type OpSingle = Result<Box<dyn Fn(&Value) -> bool + Send + Sync>>;
 
 pub trait EqMaker : SupportedColType {
     fn make_eq() -> OpDouble
     {
-        Err(From::from(format!("{} does not support equality comparison", Self::str_type())))
+        Err(Self::unsupported("equality comparison"))
     }
 
-    fn make_eq_left_const(left:&String) -> OpSingle
+    fn make_eq_left_const(_left:&str) -> OpSingle
     {
-        Err(From::from(format!("{} does not support equality comparison", Self::str_type())))        
+        Err(Self::unsupported("equality comparison"))        
     }
 
     fn make_neq() -> OpDouble
     {
-        Err(From::from(format!("{} does not support equality comparison", Self::str_type())))
+        Err(Self::unsupported("equality comparison"))
     }
 
-    fn make_neq_left_const(left:&String) -> OpSingle
+    fn make_neq_left_const(_left:&str) -> OpSingle
     {
-        Err(From::from(format!("{} does not support equality comparison", Self::str_type())))        
+        Err(Self::unsupported("equality comparison"))        
     }
 }
 
@@ -46,82 +73,56 @@ impl<T : SupportedColType + PartialEq + FromStr + 'static> EqMaker for T
 {
     fn make_eq() -> OpDouble
     {
-        Ok(
-            Box::new(
-                |a,b|{ 
-                    let a = a.parse::<T>().map_err(|_|{Self::parse_err(a)})?;
-                    let b = b.parse::<T>().map_err(|_|{Self::parse_err(b)})?;
-                    Ok(a == b)
-                 }
-            ))
-    }
-    fn make_eq_left_const(left:&String) -> OpSingle
+        Ok(Box::new(|a,b|{ a == b }))
+    }
+    fn make_eq_left_const(left:&str) -> OpSingle
     {
-        let left_c = left.parse::<T>().map_err(|_|{Self::parse_err(left)})?;
-        Ok(
-            Box::new(
-                move |x|{ 
-                    let x = x.parse::<T>().map_err(|_|{Self::parse_err(x)})?;
-                    Ok(left_c == x)
-                 }
-            ))        
+        let left_c = Self::make_value(left)?;
+        Ok(Box::new(move |x|{ left_c == *x }))
     }
     fn make_neq() -> OpDouble
     {
-        Ok(
-            Box::new(
-                |a,b|{ 
-                    let a = a.parse::<T>().map_err(|_|{Self::parse_err(a)})?;
-                    let b = b.parse::<T>().map_err(|_|{Self::parse_err(b)})?;
-                    Ok(a != b)
-                 }
-            ))
-    }
-    fn make_neq_left_const(left:&String) -> OpSingle
+        Ok(Box::new(|a,b|{ a != b }))
+    }
+    fn make_neq_left_const(left:&str) -> OpSingle
     {
-        let left_c = left.parse::<T>().map_err(|_|{Self::parse_err(left)})?;
-        Ok(
-            Box::new(
-                move |x|{ 
-                    let x = x.parse::<T>().map_err(|_|{Self::parse_err(x)})?;
-                    Ok(left_c != x)
-                 }
-            ))        
+        let left_c = Self::make_value(left)?;
+        Ok(Box::new(move |x|{ left_c != *x }))
     }
 }
 
 pub trait CompMaker : SupportedColType {
     fn make_lt() -> OpDouble
     {
-        Err(From::from(format!("{} does not support order comparison", Self::str_type())))
+        Err(Self::unsupported("order comparison"))
     }
-    fn make_lt_left_const(left:&String) -> OpSingle
+    fn make_lt_left_const(_left:&str) -> OpSingle
     {
-        Err(From::from(format!("{} does not support order comparison", Self::str_type())))        
+        Err(Self::unsupported("order comparison"))        
     }
     fn make_leq() -> OpDouble
     {
-        Err(From::from(format!("{} does not support order comparison", Self::str_type())))
+        Err(Self::unsupported("order comparison"))
     }
-    fn make_leq_left_const(left:&String) -> OpSingle
+    fn make_leq_left_const(_left:&str) -> OpSingle
     {
-        Err(From::from(format!("{} does not support order comparison", Self::str_type())))        
+        Err(Self::unsupported("order comparison"))        
     }
     fn make_gt() -> OpDouble
     {
-        Err(From::from(format!("{} does not support order comparison", Self::str_type())))
+        Err(Self::unsupported("order comparison"))
     }
-    fn make_gt_left_const(left:&String) -> OpSingle
+    fn make_gt_left_const(_left:&str) -> OpSingle
     {
-        Err(From::from(format!("{} does not support order comparison", Self::str_type())))        
+        Err(Self::unsupported("order comparison"))        
     }
     fn make_geq() -> OpDouble
     {
-        Err(From::from(format!("{} does not support order comparison", Self::str_type())))
+        Err(Self::unsupported("order comparison"))
     }
-    fn make_geq_left_const(left:&String) -> OpSingle
+    fn make_geq_left_const(_left:&str) -> OpSingle
     {
-        Err(From::from(format!("{} does not support order comparison", Self::str_type())))        
+        Err(Self::unsupported("order comparison"))        
     }
 }
 
@@ -129,102 +130,59 @@ impl<T : SupportedColType + PartialOrd + FromStr + 'static> CompMaker for T
 {
     fn make_lt() -> OpDouble
     {
-        Ok(
-            Box::new(
-                |a,b|{ 
-                    let a = a.parse::<T>().map_err(|_|{Self::parse_err(a)})?;
-                    let b = b.parse::<T>().map_err(|_|{Self::parse_err(b)})?;                
-                    Ok(a < b)
-                 }
-            ))
-    }
-    fn make_lt_left_const(left:&String) -> OpSingle
+        Ok(Box::new(|a,b|{ a < b }))
+    }
+    fn make_lt_left_const(left:&str) -> OpSingle
     {
-        let left_c = left.parse::<T>().map_err(|_|{Self::parse_err(left)})?;
-        Ok(
-            Box::new(
-                move |x|{ 
-                    let x = x.parse::<T>().map_err(|_|{Self::parse_err(x)})?;
-                    Ok(left_c < x)
-                 }
-            ))        
+        let left_c = Self::make_value(left)?;
+        Ok(Box::new(move |x|{ left_c < *x }))
     }
     fn make_leq() -> OpDouble
     {
-        Ok(
-            Box::new(
-                |a,b|{ 
-                    let a = a.parse::<T>().map_err(|_|{Self::parse_err(a)})?;
-                    let b = b.parse::<T>().map_err(|_|{Self::parse_err(b)})?;                
-                    Ok(a <= b)
-                 }
-            ))
-    }
-    fn make_leq_left_const(left:&String) -> OpSingle
+        Ok(Box::new(|a,b|{ a <= b }))
+    }
+    fn make_leq_left_const(left:&str) -> OpSingle
     {
-        let left_c = left.parse::<T>().map_err(|_|{Self::parse_err(left)})?;
-        Ok(
-            Box::new(
-                move |x|{ 
-                    let x = x.parse::<T>().map_err(|_|{Self::parse_err(x)})?;
-                    Ok(left_c <= x)
-                 }
-            ))        
+        let left_c = Self::make_value(left)?;
+        Ok(Box::new(move |x|{ left_c <= *x }))
     }
-
     fn make_gt() -> OpDouble
     {
-        Ok(
-            Box::new(
-                |a,b|{ 
-                    let a = a.parse::<T>().map_err(|_|{Self::parse_err(a)})?;
-                    let b = b.parse::<T>().map_err(|_|{Self::parse_err(b)})?;           
-                    Ok(a > b)
-                 }
-            ))
-    }
-    fn make_gt_left_const(left:&String) -> OpSingle
+        Ok(Box::new(|a,b|{ a > b }))
+    }
+    fn make_gt_left_const(left:&str) -> OpSingle
     {
-        let left_c = left.parse::<T>().map_err(|_|{Self::parse_err(left)})?;
-        Ok(
-            Box::new(
-                move |x|{ 
-                    let x = x.parse::<T>().map_err(|_|{Self::parse_err(x)})?;
-                    Ok(left_c > x)
-                 }
-            ))        
+        let left_c = Self::make_value(left)?;
+        Ok(Box::new(move |x|{ left_c > *x }))
     }
     fn make_geq() -> OpDouble
     {
-        Ok(
-            Box::new(
-                |a,b|{ 
-                    let a = a.parse::<T>().map_err(|_|{Self::parse_err(a)})?;
-                    let b = b.parse::<T>().map_err(|_|{Self::parse_err(b)})?;           
-                    Ok(a >= b)
-                 }
-            ))
-    }
-    fn make_geq_left_const(left:&String) -> OpSingle
+        Ok(Box::new(|a,b|{ a >= b }))
+    }
+    fn make_geq_left_const(left:&str) -> OpSingle
     {
-        let left_c = left.parse::<T>().map_err(|_|{Self::parse_err(left)})?;
-        Ok(
-            Box::new(
-                move |x|{ 
-                    let x = x.parse::<T>().map_err(|_|{Self::parse_err(x)})?;
-                    Ok(left_c >= x)
-                 }
-            ))        
+        let left_c = Self::make_value(left)?;
+        Ok(Box::new(move |x|{ left_c >= *x }))
     }
 }
 
-type OpMakerDouble = Box<Fn() -> OpDouble>;
-type OpMakerSingle = Box<Fn(&String) -> OpSingle>;
+type OpMakerDouble = Box<dyn Fn() -> OpDouble + Send + Sync>;
+type OpMakerSingle = Box<dyn Fn(&str) -> OpSingle + Send + Sync>;
 
 type MakerPair = (OpMakerDouble, OpMakerSingle);
 
+pub type ValueMaker = Box<dyn Fn(&str) -> Result<Value> + Send + Sync>;
+
+// A ValueMaker shared between a column's values and the constants compared with it
+type SharedValueMaker = Arc<dyn Fn(&str) -> Result<Value> + Send + Sync>;
+
 pub struct ColType {
     pub name : String,
+    // the type's parameters and the format cells of this column are written in, if the
+    // header gave them
+    pub params : Option<String>,
+    pub format : Option<String>,
+    pub value : ValueMaker,
     pub eq : MakerPair,
     pub neq : MakerPair,
     pub lt : MakerPair,
@@ -234,9 +192,20 @@ pub struct ColType {
 }
 
 impl ColType {
-    fn make<T : EqMaker + CompMaker + SupportedColType + 'static>() -> Self {
-        ColType { 
+    fn make<T : EqMaker + CompMaker + SupportedColType + 'static>(params:Option<&str>, format:Option<&str>) -> Result<Self> {
+        let value : ValueMaker =
+            match (params, format) {
+                (None, None) => Box::new(T::make_value),
+                (Some(p), None) => T::with_params(p)?,
+                (None, Some(f)) => T::formatted(f)?,
+                (Some(p), Some(f)) =>
+                    return Err(CsvFiltError::Schema(format!("type '{}' can't take both parameters '({})' and a format '{}'", T::str_type(), p, f)))
+            };
+        Ok(ColType { 
             name : <T as SupportedColType>::str_type(), 
+            params : params.map(|p|{ p.to_owned() }),
+            format : format.map(|f|{ f.to_owned() }),
+            value,
             eq : 
                 (Box::new(<T as EqMaker>::make_eq), Box::new(<T as EqMaker>::make_eq_left_const)), 
             neq : 
@@ -249,7 +218,72 @@ impl ColType {
                 (Box::new(<T as CompMaker>::make_gt), Box::new(<T as CompMaker>::make_gt_left_const)), 
             geq : 
                 (Box::new(<T as CompMaker>::make_geq), Box::new(<T as CompMaker>::make_geq_left_const)) 
+            })
+    }
+
+    // `enum(BUY,SELL)`, whose cells must be one of the values given. They are read as their
+    // position in the list, so compare as cheaply as integers and order as declared, and a
+    // constant in the query that isn't one of them is an error when the query is compiled.
+    fn make_enum(params:Option<&str>, format:Option<&str>) -> Result<Self> {
+        let params = params.ok_or_else(||{ CsvFiltError::Schema("enum needs its values, as in enum(BUY,SELL)".to_owned()) })?;
+        if let Some(f) = format {
+            return Err(CsvFiltError::Schema(format!("type 'enum' does not take a format, found '{}'", f)));
+        }
+        let values : Vec<String> = params.split(',').map(|v|{ v.trim().to_owned() }).collect();
+        for (i, v) in values.iter().enumerate() {
+            if v.is_empty() || values[..i].contains(v) {
+                return Err(CsvFiltError::Schema(format!("enum({}) has an empty or repeated value", params)));
+            }
+        }
+
+        let type_name = format!("enum({})", params);
+        let read : SharedValueMaker = Arc::new(move |s|{
+            match values.iter().position(|v|{ v == s }) {
+                Some(code) => Ok(Value::Enum(code as u32)),
+                None => Err(CsvFiltError::CellParse { column : None, record : None, value : s.to_owned(), type_name : type_name.clone() })
             }
+        });
+        let ops = |cmp:fn(&Value, &Value) -> bool| -> MakerPair {
+            let read = read.clone();
+            (Box::new(move ||{ Ok(Box::new(cmp)) }),
+             Box::new(move |left|{
+                 let left_c = read(left)?;
+                 Ok(Box::new(move |x|{ cmp(&left_c, x) }))
+             }))
+        };
+        Ok(ColType {
+            name : "enum".to_owned(),
+            params : Some(params.to_owned()),
+            format : None,
+            eq : ops(|a, b|{ a == b }),
+            neq : ops(|a, b|{ a != b }),
+            lt : ops(|a, b|{ a < b }),
+            leq : ops(|a, b|{ a <= b }),
+            gt : ops(|a, b|{ a > b }),
+            geq : ops(|a, b|{ a >= b }),
+            value : Box::new(move |s|{ read(s) })
+        })
+    }
+
+    // Whether columns of the two types can be compared. Formats and a decimal's precision
+    // only change how cells are read, but an enum's codes only mean anything in its own list.
+    pub fn compares_with(&self, other:&ColType) -> bool {
+        self.name == other.name && (self.name != "enum" || self.params == other.params)
+    }
+
+    // The type as written in a header, with its parameters and format if it has them
+    pub fn spec(&self, nullable:bool) -> String {
+        let mut spec = self.name.clone();
+        if let Some(ref p) = self.params {
+            spec.push_str(&format!("({})", p));
+        }
+        if nullable {
+            spec.push('?');
+        }
+        if let Some(ref f) = self.format {
+            spec.push_str(&format!(":{}", f));
+        }
+        spec
     }
 }
 
@@ -257,89 +291,388 @@ impl SupportedColType for String {
     fn str_type() -> String {
         "string".to_owned()
     }
+
+    fn into_value(self) -> Value {
+        Value::Str(self)
+    }
 }
 
-impl SupportedColType for f32 {
+impl SupportedColType for f64 {
     fn str_type() -> String {
         "float".to_owned()
     }
+
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+// Exact, so prices compare and add up as written. With `decimal(p,s)` a cell may have at
+// most `p` digits, `s` of them after the point.
+impl SupportedColType for Decimal {
+    fn str_type() -> String {
+        "decimal".to_owned()
+    }
+
+    fn into_value(self) -> Value {
+        Value::Decimal(self)
+    }
+
+    fn with_params(params:&str) -> Result<ValueMaker> {
+        let bad = ||{ CsvFiltError::Schema(format!("Expected decimal(precision,scale) with a scale no more than a precision of 1 to {}, found decimal({})", Decimal::MAX_SCALE, params)) };
+        let (p, s) = params.split_once(',').ok_or_else(bad)?;
+        let (p, s) : (u32, u32) = (p.trim().parse().map_err(|_|{ bad() })?, s.trim().parse().map_err(|_|{ bad() })?);
+        if p == 0 || p > Decimal::MAX_SCALE || s > p {
+            return Err(bad());
+        }
+        let limit = Decimal::from(10i128.pow(p - s));
+        Ok(Box::new(move |c|{
+            match c.parse::<Decimal>() {
+                Ok(d) if d.normalize().scale() <= s && d.trunc().abs() < limit => Ok(Value::Decimal(d)),
+                _ => Err(Self::parse_err(c))
+            }
+        }))
+    }
 }
 
 impl SupportedColType for bool {
     fn str_type() -> String {
         "bool".to_owned()
     }
+
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
 }
 
-impl SupportedColType for i32 {
-    fn str_type() -> String {
-        "int".to_owned()
+// Every integer type is held at full width, signed ones as Int and unsigned ones as UInt, so
+// arithmetic can check results against the type it gives rather than overflowing
+macro_rules! int_col_type {
+    ($t:ty, $name:expr, $variant:ident) => {
+        impl SupportedColType for $t {
+            fn str_type() -> String {
+                $name.to_owned()
+            }
+
+            fn into_value(self) -> Value {
+                Value::$variant(self.into())
+            }
+        }
     }
 }
 
-use std::rc::Rc;
+int_col_type!(i8, "i8", Int);
+int_col_type!(i16, "i16", Int);
+int_col_type!(i32, "i32", Int);
+int_col_type!(i64, "int", Int);
+int_col_type!(i128, "i128", Int);
+int_col_type!(u8, "u8", UInt);
+int_col_type!(u16, "u16", UInt);
+int_col_type!(u32, "u32", UInt);
+int_col_type!(u64, "u64", UInt);
+int_col_type!(u128, "u128", UInt);
+
+// parameters, then format
+type ColTypeMaker = fn(Option<&str>, Option<&str>) -> Result<ColType>;
+
+// Other names a header can give a type
+const ALIASES : &[(&str, &str)] = &[("long", "int"), ("i64", "int"), ("f64", "float")];
 
+// Columns whose types have no parameters or format share one ColType per type; others get
+// their own. An enum has no shared one, as it can't be read without its values.
 struct ColTypes {
-    pickers : Vec<Rc<ColType>>
+    types : Vec<(String, ColTypeMaker, Option<Arc<ColType>>)>
+}
+
+fn maker<T : EqMaker + CompMaker + SupportedColType + 'static>() -> (String, ColTypeMaker) {
+    (T::str_type(), ColType::make::<T>)
 }
 
 impl ColTypes {
     fn make() -> Self {
+        let makers : Vec<(String, ColTypeMaker)> = vec!(
+            maker::<String>(),
+            maker::<i64>(),
+            maker::<i8>(),
+            maker::<i16>(),
+            maker::<i32>(),
+            maker::<i128>(),
+            maker::<u8>(),
+            maker::<u16>(),
+            maker::<u32>(),
+            maker::<u64>(),
+            maker::<u128>(),
+            maker::<f64>(),
+            maker::<Decimal>(),
+            maker::<bool>(),
+            maker::<NaiveDate>(),
+            maker::<NaiveTime>(),
+            maker::<DateTime<Utc>>(),
+            ("enum".to_owned(), ColType::make_enum)
+        );
         ColTypes {
-            pickers: vec!(
-                Rc::new(ColType::make::<String>()),
-                Rc::new(ColType::make::<i32>()),
-                Rc::new(ColType::make::<f32>()),
-                Rc::new(ColType::make::<bool>())
-            )
+            types : makers.into_iter().map(|(name, m)|{ (name, m, m(None, None).ok().map(Arc::new)) }).collect()
         }
     }
 
-    fn find(&self, name:&String) -> Result<Rc<ColType>, Box<Error>>
+    fn find(&self, name:&str, params:Option<&str>, format:Option<&str>) -> Result<Arc<ColType>>
     {
-        let o = self.pickers.iter().find(|p|{ p.name == *name }).map(|p|{ p.clone() });
-        o.ok_or(From::from(format!("unable to find type matching '{}'", *name)))
+        let name = ALIASES.iter().find(|a|{ a.0 == name }).map_or(name, |a|{ a.1 });
+        let &(_, make, ref shared) = self.types.iter().find(|t|{ t.0 == name })
+            .ok_or_else(||{ CsvFiltError::Schema(format!("unable to find type matching '{}'", name)) })?;
+        match *shared {
+            Some(ref t) if params.is_none() && format.is_none() => Ok(t.clone()),
+            _ => Ok(Arc::new(make(params, format)?))
+        }
     }
 }
 
 pub struct ColItem {
     name : String,
-    pub col_type : Rc<ColType>
+    pub col_type : Arc<ColType>,
+    // the cell values read as null, for a column marked nullable with `?`
+    pub nulls : Option<Arc<[String]>>
 }
 
 impl ColItem {
-    fn parse(s:&String, types : &ColTypes) -> Result<Self, Box<Error>> 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The column's type as written in a header
+    pub fn spec(&self) -> String {
+        self.col_type.spec(self.nulls.is_some())
+    }
+
+    fn parse(s:&String, types : &ColTypes, nulls:&Arc<[String]>) -> Result<Self> 
     {
         use regex::Regex;
         lazy_static! {
-            static ref REGEX: Regex = Regex::new(r"^(?P<colname>\w+)\[(?P<coltype>\w+)\]$").unwrap();
+            static ref REGEX: Regex = Regex::new(r"^(?P<colname>[^\[\]]+)\[(?P<coltype>\w+)(\((?P<params>[^()]*)\))?(?P<nullable>\?)?(:(?P<format>[^\]]+))?\]$").unwrap();
         }
         use regex::Captures;
         let caps = REGEX.captures_iter(s).collect::<Vec<Captures>>();
         if caps.len() == 1 {
             let colname = caps[0]["colname"].to_owned();
-            let coltype = caps[0]["coltype"].to_owned();
-            Ok(ColItem { name : colname, col_type : types.find(&coltype)? })
+            let params = caps[0].name("params").map(|m|{ m.as_str() });
+            let format = caps[0].name("format").map(|m|{ m.as_str() });
+            let nulls = caps[0].name("nullable").map(|_|{ nulls.clone() });
+            Ok(ColItem { name : colname, col_type : types.find(&caps[0]["coltype"], params, format)?, nulls })
         }
         else {
-            Err(From::from(format!("Failed to parse {} to a name/type pair", s)))
+            Err(CsvFiltError::Schema(format!("Failed to parse {} to a name/type pair", s)))
+        }
+    }
+}
+
+impl PartialEq for ColItem {
+    fn eq(&self, other:&ColItem) -> bool {
+        self.name == other.name && self.spec() == other.spec()
+    }
+}
+
+// Optimal string alignment distance, counted in chars. As well as inserting, deleting and
+// changing a char, swapping two adjacent chars is one edit, as it is the commonest typo.
+fn edit_distance(a:&str, b:&str) -> usize {
+    let (a, b) : (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // d[i][j] is the distance between the first i chars of a and the first j of b
+    let mut d : Vec<Vec<usize>> = (0..=a.len()).map(|i|{ (0..=b.len()).map(|j|{ i.max(j) }).collect() }).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut best = (d[i - 1][j - 1] + cost).min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(d[i - 2][j - 2] + 1);
+            }
+            d[i][j] = best;
         }
     }
+    d[a.len()][b.len()]
 }
 
+/// The typed columns of an input, in order
 pub struct Schema (Vec<ColItem>);
 
+impl PartialEq for Schema {
+    fn eq(&self, other:&Schema) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cols : Vec<String> = 
+            self.0.iter().map(|c|{ format!("{}[{}]", c.name, c.spec()) }).collect();
+        write!(f, "{}", cols.join(","))
+    }
+}
+
 impl Schema {
-    pub fn from_header(header:&Vec<String>) -> Result<Schema, Box<Error>> {
+    /// Reads a header such as `stock[string],price[float]`. Date, time and timestamp columns
+    /// are ISO-8601 unless the header gives a strftime format, as in `ts[timestamp:%Y%m%d-%H:%M:%S%.f]`.
+    ///
+    /// Prices and other amounts that must compare exactly are `decimal`, or `decimal(p,s)` to
+    /// allow at most `p` digits with `s` after the point (quoted in the CSV header, for the
+    /// comma); `float` is an `f64`.
+    ///
+    /// Integers are `i8` to `i128` and `u8` to `u128`, with `int` (or `long`) being `i64`.
+    ///
+    /// A column whose cells come from a small known set can be an enum, e.g.
+    /// `side[enum(BUY,SELL)]` (quoted in the CSV header, for the comma). Cells and constants
+    /// in the query must be one of its values, and compare in the order they are listed.
+    ///
+    /// A `?` after the type, as in `size[int?]` or `ts[timestamp?:%Y%m%d]`, lets the column
+    /// hold nulls, which are empty cells.
+    pub fn from_header(header:&StringRecord) -> Result<Schema> {
+        Schema::from_header_with_null_values(header, &[""])
+    }
+
+    /// As [`Schema::from_header`], with nullable columns reading any of `null_values` as null
+    pub fn from_header_with_null_values(header:&StringRecord, null_values:&[&str]) -> Result<Schema> {
         let types = ColTypes::make();
+        let nulls : Arc<[String]> = null_values.iter().map(|n|{ n.trim().to_owned() }).collect();
 
-        let items : Result<Vec<_>,Box<Error>> = 
-            header.iter().map(|c|{ ColItem::parse(c, &types) }).collect();
+        let items : Result<Vec<_>> = 
+            header.iter().map(|c|{ ColItem::parse(&c.to_owned(), &types, &nulls) }).collect();
 
         Ok(Schema(items?))
     }
 
-    pub fn try_find_col(&self, name:&String) -> Option<(usize, &ColItem)> {
-        self.0.iter().enumerate().find(|&x|{x.1.name == *name})
+    /// The position and details of the column called `name`
+    pub fn try_find_col(&self, name:&str) -> Option<(usize, &ColItem)> {
+        self.0.iter().enumerate().find(|&x|{x.1.name == name})
+    }
+
+    /// The column with the name closest to `name`, if one is close enough to be a likely typo
+    pub fn suggest(&self, name:&str) -> Option<&str> {
+        let limit = (name.chars().count() / 3).max(1);
+        self.0.iter()
+            .map(|c|{ (edit_distance(&name.to_lowercase(), &c.name.to_lowercase()), c) })
+            .filter(|&(d, _)|{ d <= limit })
+            .min_by_key(|&(d, _)|{ d })
+            .map(|(_, c)|{ c.name() })
+    }
+
+    pub fn col(&self, idx:usize) -> &ColItem {
+        &self.0[idx]
     }
-}
\ No newline at end of file
+
+    pub fn iter(&self) -> ::std::slice::Iter<'_, ColItem> {
+        self.0.iter()
+    }
+
+    /// The number of columns
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// Schemas for tests elsewhere to parse queries against
+#[cfg(test)]
+impl Schema {
+    // The schema of a header of `columns`
+    pub fn of(columns:&[&str]) -> Schema {
+        Schema::from_header(&StringRecord::from(columns.to_vec())).unwrap()
+    }
+
+    // stock[string],price[float],size[int],executed[bool], the columns of most examples
+    pub fn trades() -> Schema {
+        Schema::of(&["stock[string]", "price[float]", "size[int]", "executed[bool]"])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::StringRecord;
+
+    use schema::{edit_distance, Schema};
+    use value::Value;
+
+    fn schema(header:Vec<&str>) -> Result<Schema, String> {
+        Schema::from_header(&StringRecord::from(header)).map_err(|e|{ e.to_string() })
+    }
+
+    #[test]
+    fn header_formats_are_kept_and_checked() {
+        let s = schema(vec!("ts[timestamp:%Y%m%d-%H:%M:%S%.f]", "d[date?:%d/%m/%Y]", "t[time]")).unwrap();
+        assert_eq!("ts[timestamp:%Y%m%d-%H:%M:%S%.f],d[date?:%d/%m/%Y],t[time]", s.to_string());
+        assert!(s == schema(vec!("ts[timestamp:%Y%m%d-%H:%M:%S%.f]", "d[date?:%d/%m/%Y]", "t[time]")).unwrap());
+        assert!(s != schema(vec!("ts[timestamp]", "d[date?:%d/%m/%Y]", "t[time]")).unwrap());
+
+        assert_eq!("'%Q' is not a valid format for a date", schema(vec!("d[date:%Q]")).err().unwrap());
+        assert_eq!("type 'int' does not take a format, found '%Y'", schema(vec!("n[int:%Y]")).err().unwrap());
+    }
+
+    #[test]
+    fn sized_integers_hold_only_their_range() {
+        let s = schema(vec!("a[i8]", "b[u8]", "c[long]", "d[u128]")).unwrap();
+        let read = |col, cell|{ (s.col(col).col_type.value)(cell) };
+        assert_eq!(Value::Int(-128), read(0, "-128").unwrap());
+        assert!(read(0, "128").is_err());
+        assert_eq!(Value::UInt(255), read(1, "255").unwrap());
+        assert!(read(1, "-1").is_err());
+        assert_eq!(Value::Int(3000000000), read(2, "3000000000").unwrap());
+        assert_eq!(Value::UInt(u128::MAX), read(3, "340282366920938463463374607431768211455").unwrap());
+        assert_eq!("a[i8],b[u8],c[int],d[u128]", s.to_string());
+    }
+
+    #[test]
+    fn decimals_are_read_to_their_precision_and_scale() {
+        let s = schema(vec!("price[decimal(6,2)]", "any[decimal]", "fx[float]")).unwrap();
+        let read = |col, cell|{ (s.col(col).col_type.value)(cell) };
+        assert_eq!(Value::Decimal("9999.99".parse().unwrap()), read(0, "9999.99").unwrap());
+        assert_eq!(Value::Decimal("0.3".parse().unwrap()), read(0, "0.300").unwrap());
+        for too_precise in &["0.305", "10000.00"] {
+            assert_eq!(format!("Could not make a decimal from '{}'", too_precise), read(0, too_precise).err().unwrap().to_string());
+        }
+        assert_eq!(Value::Decimal("0.305".parse().unwrap()), read(1, "0.305").unwrap());
+        assert_eq!(Value::Float(0.1), read(2, "0.1").unwrap());
+        assert_eq!("price[decimal(6,2)],any[decimal],fx[float]", s.to_string());
+
+        for bad in &["price[decimal(2,3)]", "price[decimal(0,0)]", "price[decimal(29,2)]", "price[decimal(6)]", "price[float(2,3)]"] {
+            assert!(schema(vec!(*bad)).is_err(), "for {}", bad);
+        }
+    }
+
+    #[test]
+    fn enums_read_only_their_values_as_codes() {
+        let s = schema(vec!("side[enum(BUY, SELL)]", "venue[enum(LSE,XETRA)?]")).unwrap();
+        let side = &s.col(0).col_type;
+        assert_eq!(Value::Enum(0), (side.value)("BUY").unwrap());
+        assert_eq!(Value::Enum(1), (side.value)("SELL").unwrap());
+        assert_eq!("Could not make a enum(BUY, SELL) from 'HOLD'", (side.value)("HOLD").err().unwrap().to_string());
+        assert_eq!("side[enum(BUY, SELL)],venue[enum(LSE,XETRA)?]", s.to_string());
+
+        for bad in &["side[enum]", "side[enum()]", "side[enum(BUY,,SELL)]", "side[enum(BUY,BUY)]", "side[enum(BUY):x]"] {
+            assert!(schema(vec!(*bad)).is_err(), "for {}", bad);
+        }
+    }
+
+    #[test]
+    fn enums_only_compare_with_the_same_values() {
+        let s = schema(vec!("a[enum(BUY,SELL)]", "b[enum(BUY,SELL)?]", "c[enum(SELL,BUY)]", "d[decimal(6,2)]", "e[decimal]")).unwrap();
+        let t = |i|{ &s.col(i).col_type };
+        assert!(t(0).compares_with(t(1)));
+        assert!(!t(0).compares_with(t(2)));
+        assert!(t(3).compares_with(t(4)));
+    }
+
+    #[test]
+    fn swapped_letters_are_one_edit() {
+        assert_eq!(1, edit_distance("pirce", "price"));
+        assert_eq!(1, edit_distance("stcok", "stock"));
+        assert_eq!(1, edit_distance("stok", "stock"));
+        assert_eq!(2, edit_distance("sotkc", "stock"));
+        assert_eq!(3, edit_distance("", "abc"));
+        assert_eq!(0, edit_distance("size", "size"));
+
+        let s = schema(vec!("stock[string]", "price[float]", "size[int]")).unwrap();
+        assert_eq!(Some("price"), s.suggest("pirce"));
+        assert_eq!(Some("stock"), s.suggest("STCOK"));
+        assert_eq!(None, s.suggest("volume"));
+    }
+}
@@ -0,0 +1,139 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::PathBuf;
+
+use glob;
+use memmap2::Mmap;
+
+use csvfilt::{CsvFiltError, Result};
+
+use compression;
+use compression::Compression;
+
+pub enum FileSource {
+    ReadFromFile(PathBuf),
+    Stdin
+}
+
+impl FileSource {
+    pub fn from_arg(arg:OsString) -> Result<Vec<FileSource>> {
+        if arg == "-" {
+            return Ok(vec!(FileSource::Stdin));
+        }
+
+        let pattern = arg.to_string_lossy().into_owned();
+        let is_glob = pattern.contains(|c|{ c == '*' || c == '?' || c == '[' });
+        if !is_glob {
+            return Ok(vec!(FileSource::ReadFromFile(From::from(arg))));
+        }
+
+        let mut sources = Vec::new();
+        let entries = glob::glob(&pattern)
+            .map_err(|e|{ CsvFiltError::Usage(format!("Bad pattern '{}': {}", pattern, e)) })?;
+        for entry in entries {
+            let path = entry.map_err(|e|{
+                let context = format!("Could not read '{}'", e.path().display());
+                CsvFiltError::io(context, e.into())
+            })?;
+            sources.push(FileSource::ReadFromFile(path));
+        }
+        if sources.is_empty() {
+            Err(CsvFiltError::Usage(format!("No files match '{}'", pattern)))
+        }
+        else {
+            Ok(sources)
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            FileSource::ReadFromFile(ref p) => p.display().to_string(),
+            FileSource::Stdin => "-".to_owned()
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Read>> {
+        match *self {
+            FileSource::ReadFromFile(ref p) => {
+                let f = File::open(p)
+                    .map_err(|e|{ CsvFiltError::io(format!("Could not open '{}'", p.display()), e) })?;
+                compression::decompress(Box::new(f), Some(p))
+            }
+            FileSource::Stdin => {
+                compression::decompress(Box::new(io::stdin().lock()), None)
+            }
+        }
+    }
+
+    // Maps the file into memory if it is a regular, non-empty file of plain CSV, so that
+    // its records can be split between threads and parsed where they lie. Compressed files
+    // have to be decoded in order, so aren't mapped.
+    pub fn map(&self) -> Result<Option<Mmap>> {
+        let p =
+            match *self {
+                FileSource::ReadFromFile(ref p) => p,
+                FileSource::Stdin => return Ok(None)
+            };
+        let f = File::open(p)
+            .map_err(|e|{ CsvFiltError::io(format!("Could not open '{}'", p.display()), e) })?;
+        let meta = f.metadata()
+            .map_err(|e|{ CsvFiltError::io(format!("Could not read '{}'", p.display()), e) })?;
+        if !meta.is_file() || meta.len() == 0 {
+            return Ok(None);
+        }
+        // Safety: the map is only read, but it is undefined behaviour (or a SIGBUS) if another
+        // process truncates or rewrites the file while it is mapped. That is why mapping is
+        // only done when asked for with --mmap.
+        let m = unsafe { Mmap::map(&f) }
+            .map_err(|e|{ CsvFiltError::io(format!("Could not map '{}'", p.display()), e) })?;
+        match Compression::detect(&m, Some(p)) {
+            Compression::Plain => Ok(Some(m)),
+            _ => Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use std::process;
+
+    use compression::{Compression, CompressedWriter};
+    use source::FileSource;
+
+    const CSV : &str = "a[int],b[string]\n1,x\n2,y\n";
+
+    fn write(ext:&str, c:Compression, contents:&str) -> PathBuf {
+        let mut w = CompressedWriter::new(Vec::new(), c).unwrap();
+        w.write_all(contents.as_bytes()).unwrap();
+        let path = env::temp_dir().join(format!("csvfilt-source-{}.{}", process::id(), ext));
+        fs::write(&path, w.finish().unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn only_plain_files_are_mapped() {
+        let path = write("csv", Compression::Plain, CSV);
+        assert_eq!(CSV.as_bytes(), &FileSource::ReadFromFile(path.clone()).map().unwrap().unwrap()[..]);
+        fs::remove_file(&path).unwrap();
+
+        for &(c, ext) in [(Compression::Gzip, "csv.gz"), (Compression::Zstd, "csv.zst")].iter() {
+            let path = write(ext, c, CSV);
+            let source = FileSource::ReadFromFile(path.clone());
+            assert!(source.map().unwrap().is_none());
+            let mut out = String::new();
+            source.open().unwrap().read_to_string(&mut out).unwrap();
+            assert_eq!(CSV, out);
+            fs::remove_file(&path).unwrap();
+        }
+
+        let path = write("empty.csv", Compression::Plain, "");
+        assert!(FileSource::ReadFromFile(path.clone()).map().unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
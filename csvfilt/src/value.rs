@@ -0,0 +1,150 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+
+// A typed cell value, used where the query needs the value itself rather than a comparison
+// of it, e.g. computed columns and sorting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    // any signed integer type
+    Int(i128),
+    // any unsigned integer type
+    UInt(u128),
+    Float(f64),
+    Decimal(Decimal),
+    Bool(bool),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    Timestamp(DateTime<Utc>),
+    // an enum value, as its position in the enum's list
+    Enum(u32),
+    // a cell of a nullable column holding one of the null values, or a result computed from one
+    Null
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Int(i) => Some(i as f64),
+            Value::UInt(u) => Some(u as f64),
+            Value::Float(f) => Some(f),
+            Value::Decimal(d) => d.to_f64(),
+            _ => None
+        }
+    }
+
+    pub fn as_i128(&self) -> Option<i128> {
+        match *self {
+            Value::Int(i) => Some(i),
+            Value::UInt(u) => i128::try_from(u).ok(),
+            _ => None
+        }
+    }
+
+    pub fn as_u128(&self) -> Option<u128> {
+        match *self {
+            Value::Int(i) => u128::try_from(i).ok(),
+            Value::UInt(u) => Some(u),
+            _ => None
+        }
+    }
+
+    // Exactly, for arithmetic with decimals; None if out of a decimal's range
+    pub fn as_decimal(&self) -> Option<Decimal> {
+        match *self {
+            Value::Int(i) => Decimal::try_from_i128_with_scale(i, 0).ok(),
+            Value::UInt(u) => i128::try_from(u).ok().and_then(|i|{ Decimal::try_from_i128_with_scale(i, 0).ok() }),
+            Value::Decimal(d) => Some(d),
+            _ => None
+        }
+    }
+
+    // Total order used for sorting, with nulls first. Values of different types only meet
+    // here if they are numbers; anything else compares equal.
+    pub fn compare(&self, other:&Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Less,
+            (_, Value::Null) => Ordering::Greater,
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::UInt(a), Value::UInt(b)) => a.cmp(b),
+            (Value::Decimal(a), Value::Decimal(b)) => a.cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.cmp(b),
+            (Value::Time(a), Value::Time(b)) => a.cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.cmp(b),
+            (Value::Enum(a), Value::Enum(b)) => a.cmp(b),
+            (a, b) =>
+                match (a.as_f64(), b.as_f64()) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                    _ => Ordering::Equal
+                }
+        }
+    }
+}
+
+// Only values of the same type are ordered, as a column is only ever compared with values of
+// its own type
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other:&Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::UInt(a), Value::UInt(b)) => a.partial_cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Decimal(a), Value::Decimal(b)) => a.partial_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.partial_cmp(b),
+            (Value::Time(a), Value::Time(b)) => a.partial_cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.partial_cmp(b),
+            (Value::Enum(a), Value::Enum(b)) => a.partial_cmp(b),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Str(ref s) => write!(f, "{}", s),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::UInt(u) => write!(f, "{}", u),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Date(d) => write!(f, "{}", d),
+            Value::Time(t) => write!(f, "{}", t),
+            Value::Timestamp(t) => write!(f, "{}", t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            // enum columns are only ever written as read, so the code is never output
+            Value::Enum(code) => write!(f, "#{}", code),
+            Value::Null => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use value::Value;
+
+    #[test]
+    fn nulls_sort_first_and_are_written_empty() {
+        assert_eq!(Ordering::Less, Value::Null.compare(&Value::Int(i128::MIN)));
+        assert_eq!(Ordering::Greater, Value::Str(String::new()).compare(&Value::Null));
+        assert_eq!(Ordering::Equal, Value::Null.compare(&Value::Null));
+        // but aren't ordered against values, as comparing them is unknown
+        assert_eq!(None, Value::Null.partial_cmp(&Value::Int(1)));
+        assert_eq!("", Value::Null.to_string());
+    }
+}